@@ -0,0 +1,659 @@
+use crate::http::Request;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::io::prelude::*;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// seconds a Digest nonce can be used, browser retries with a new one after it
+const NONCE_LIFETIME: u64 = 300;
+// Basic credentials remembered after they are verified, forgotten all at once when full
+const MAX_VERIFIED: usize = 1024;
+
+// proxy authentication in config.toml
+// [auth]
+// scheme=["digest", "basic"]
+// realm="proxy"
+// file="htpasswd"
+// [[auth.user]]
+// name="alice"
+// password="$2y$05$..."
+// digest="md5 of alice:proxy:password"
+#[derive(Deserialize)]
+pub struct Auth {
+    // schemes offered to client, in order of preference
+    #[serde(default = "default_scheme")]
+    pub scheme: Vec<Scheme>,
+    #[serde(default = "default_realm")]
+    pub realm: String,
+    // htpasswd file "user:hash", or htdigest file "user:realm:md5"
+    pub file: Option<String>,
+    #[serde(default)]
+    pub user: Vec<User>,
+    // users above and in file, built when config is opened
+    #[serde(skip)]
+    users: HashMap<String, Credential>,
+    // key of Digest nonce, new one every time proxy starts
+    #[serde(skip)]
+    secret: String,
+    // md5(secret:user:password) of Basic credentials that are right
+    // bcrypt is slow on purpose, don't run it for every request of a user
+    #[serde(skip)]
+    verified: Mutex<HashSet<String>>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    Basic,
+    Digest,
+}
+
+#[derive(Deserialize)]
+pub struct User {
+    pub name: String,
+    // hash of password for Basic: bcrypt "$2y$", "$apr1$", "$1$" or "{SHA}"
+    pub password: Option<String>,
+    // md5 hex of "name:realm:password" for Digest
+    pub digest: Option<String>,
+}
+
+#[derive(Default)]
+struct Credential {
+    password: Option<String>,
+    digest: Option<String>,
+}
+
+// why a request is not authenticated
+#[derive(PartialEq, Debug)]
+pub enum Denied {
+    // no Proxy-Authorization
+    Missing,
+    // wrong user, password or format
+    Invalid,
+    // Digest nonce is too old, client can try again without asking user
+    Stale,
+}
+
+fn default_scheme() -> Vec<Scheme> {
+    vec![Scheme::Basic]
+}
+
+fn default_realm() -> String {
+    "proxy".to_owned()
+}
+
+impl Auth {
+    // read users from config and file, error message is shown when config is opened
+    pub fn load(&mut self) -> io::Result<()> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        if self.scheme.is_empty() {
+            return Err(invalid("auth without scheme".to_owned()));
+        }
+        if let Some(path) = &self.file {
+            let mut text = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut text))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+            self.load_file(&text).map_err(invalid)?;
+        }
+        for user in &self.user {
+            let credential = self.users.entry(user.name.clone()).or_default();
+            if user.password.is_some() {
+                credential.password = user.password.clone();
+            }
+            if user.digest.is_some() {
+                credential.digest = user.digest.clone();
+            }
+        }
+        for (name, credential) in &self.users {
+            if let Some(password) = &credential.password {
+                if !is_known_hash(password) {
+                    return Err(invalid(format!("password of {} is not a known hash", name)));
+                }
+            }
+            if let Some(digest) = &credential.digest {
+                let md5 = digest.len() == 32 && digest.bytes().all(|c| c.is_ascii_hexdigit());
+                if !md5 {
+                    return Err(invalid(format!("digest of {} is not md5 hex", name)));
+                }
+            }
+        }
+        self.secret = random_secret();
+        Ok(())
+    }
+
+    // "user:hash" of htpasswd, or "user:realm:md5" of htdigest
+    fn load_file(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(3, ':').collect();
+            match fields[..] {
+                [name, hash] => {
+                    let credential = self.users.entry(name.to_owned()).or_default();
+                    credential.password = Some(hash.to_owned());
+                }
+                // other realms are useless, their md5 has another realm in it
+                [name, realm, md5] if realm == self.realm => {
+                    let credential = self.users.entry(name.to_owned()).or_default();
+                    credential.digest = Some(md5.to_ascii_lowercase());
+                }
+                [_, _, _] => {}
+                _ => return Err(format!("line {} of auth file is not user:hash", i + 1)),
+            }
+        }
+        Ok(())
+    }
+
+    // name of user, or why it is denied
+    pub fn check(&self, req: &Request) -> Result<String, Denied> {
+        let value = req
+            .headers
+            .get("Proxy-Authorization")
+            .ok_or(Denied::Missing)?;
+        let value = std::str::from_utf8(value)
+            .map_err(|_| Denied::Invalid)?
+            .trim();
+        let (scheme, params) = match value.find(' ') {
+            Some(pos) => (&value[..pos], value[pos + 1..].trim()),
+            None => (value, ""),
+        };
+        if scheme.eq_ignore_ascii_case("Basic") && self.scheme.contains(&Scheme::Basic) {
+            self.check_basic(params)
+        } else if scheme.eq_ignore_ascii_case("Digest") && self.scheme.contains(&Scheme::Digest) {
+            self.check_digest(req, params)
+        } else {
+            Err(Denied::Invalid)
+        }
+    }
+
+    // "Basic base64(user:password)"
+    fn check_basic(&self, params: &str) -> Result<String, Denied> {
+        let decoded = BASE64.decode(params).map_err(|_| Denied::Invalid)?;
+        let decoded = String::from_utf8(decoded).map_err(|_| Denied::Invalid)?;
+        let colon = decoded.find(':').ok_or(Denied::Invalid)?;
+        let (name, password) = (&decoded[..colon], &decoded[colon + 1..]);
+        let key = md5_hex(&format!("{}:{}", self.secret, decoded));
+        if self.verified.lock().unwrap().contains(&key) {
+            return Ok(name.to_owned());
+        }
+        let hash = self
+            .users
+            .get(name)
+            .and_then(|credential| credential.password.as_ref())
+            .ok_or(Denied::Invalid)?;
+        if verify_password(password, hash) {
+            let mut verified = self.verified.lock().unwrap();
+            if verified.len() >= MAX_VERIFIED {
+                verified.clear();
+            }
+            verified.insert(key);
+            Ok(name.to_owned())
+        } else {
+            Err(Denied::Invalid)
+        }
+    }
+
+    // "Digest username="a", realm="proxy", nonce="..", uri="..", qop=auth,
+    // nc=00000001, cnonce="..", response="..""
+    // see RFC 7616, only MD5 is supported
+    fn check_digest(&self, req: &Request, params: &str) -> Result<String, Denied> {
+        let params = parse_params(params);
+        let param = |key: &str| params.get(key).map(|x| &x[..]).ok_or(Denied::Invalid);
+        let name = param("username")?;
+        let nonce = param("nonce")?;
+        let uri = param("uri")?;
+        let response = param("response")?;
+        let algorithm = params.get("algorithm").map_or("MD5", |x| &x[..]);
+        if param("realm")? != self.realm || !algorithm.eq_ignore_ascii_case("MD5") {
+            return Err(Denied::Invalid);
+        }
+        // credentials of another url can't be used again
+        // some clients (curl) send "/path?query" of an absolute url
+        let origin = match req.path.query() {
+            Some(query) => format!("{}?{}", req.path.path(), query),
+            None => req.path.path().to_owned(),
+        };
+        if req.path != uri && origin != uri {
+            return Err(Denied::Invalid);
+        }
+        let ha1 = self
+            .users
+            .get(name)
+            .and_then(|credential| credential.digest.as_ref())
+            .ok_or(Denied::Invalid)?;
+        let qop = match params.get("qop") {
+            Some(qop) if qop == "auth" => Some((param("nc")?, param("cnonce")?)),
+            Some(_) => return Err(Denied::Invalid),
+            None => None,
+        };
+        let expected = digest_response(ha1, nonce, qop, req.method, uri);
+        if !constant_eq(
+            expected.as_bytes(),
+            response.to_ascii_lowercase().as_bytes(),
+        ) {
+            return Err(Denied::Invalid);
+        }
+        // password is right, but nonce may be forged or too old
+        if !self.valid_nonce(nonce, now())? {
+            return Err(Denied::Stale);
+        }
+        Ok(name.to_owned())
+    }
+
+    // values of Proxy-Authenticate header
+    pub fn challenges(&self, stale: bool) -> Vec<String> {
+        self.scheme
+            .iter()
+            .map(|scheme| match scheme {
+                Scheme::Basic => format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm),
+                Scheme::Digest => format!(
+                    "Digest realm=\"{}\", qop=\"auth\", algorithm=MD5, nonce=\"{}\"{}",
+                    self.realm,
+                    self.nonce(now()),
+                    if stale { ", stale=true" } else { "" }
+                ),
+            })
+            .collect()
+    }
+
+    // nonce is "time-md5(secret:time)", proxy don't need to remember it
+    fn nonce(&self, time: u64) -> String {
+        format!(
+            "{:x}-{}",
+            time,
+            md5_hex(&format!("{}:{:x}", self.secret, time))
+        )
+    }
+
+    // Ok(false) if nonce is too old, Err if it is not created by proxy
+    fn valid_nonce(&self, nonce: &str, now: u64) -> Result<bool, Denied> {
+        let time = nonce.split('-').next().unwrap_or("");
+        let time = u64::from_str_radix(time, 16).map_err(|_| Denied::Invalid)?;
+        if !constant_eq(self.nonce(time).as_bytes(), nonce.as_bytes()) {
+            return Err(Denied::Invalid);
+        }
+        Ok(time <= now && now - time <= NONCE_LIFETIME)
+    }
+}
+
+// response = md5(HA1:nonce:nc:cnonce:qop:HA2), or md5(HA1:nonce:HA2) without qop
+// HA2 = md5(method:uri)
+fn digest_response(
+    ha1: &str,
+    nonce: &str,
+    qop: Option<(&str, &str)>,
+    method: &str,
+    uri: &str,
+) -> String {
+    let ha2 = md5_hex(&format!("{}:{}", method, uri));
+    match qop {
+        Some((nc, cnonce)) => md5_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2)),
+        None => md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2)),
+    }
+}
+
+// key=value, key="quoted, value", ...
+fn parse_params(s: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = s.trim();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq]
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        rest = rest[eq + 1..].trim_start();
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            // quoted-string, "\" escapes the next char
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+            value
+        };
+        params.insert(key, value);
+        rest = rest.trim_start().trim_start_matches(',');
+    }
+    params
+}
+
+fn is_known_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$", "$apr1$", "$1$", "{SHA}"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+// password hashes of htpasswd
+fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else if let Some(rest) = hash.strip_prefix("$apr1$") {
+        let salt = rest.split('$').next().unwrap_or("");
+        constant_eq(
+            md5_crypt(password, salt, "$apr1$").as_bytes(),
+            hash.as_bytes(),
+        )
+    } else if let Some(rest) = hash.strip_prefix("$1$") {
+        let salt = rest.split('$').next().unwrap_or("");
+        constant_eq(md5_crypt(password, salt, "$1$").as_bytes(), hash.as_bytes())
+    } else if let Some(rest) = hash.strip_prefix("{SHA}") {
+        let sha = BASE64.encode(Sha1::digest(password.as_bytes()));
+        constant_eq(sha.as_bytes(), rest.as_bytes())
+    } else {
+        false
+    }
+}
+
+// MD5-crypt of FreeBSD, used by htpasswd with magic "$apr1$"
+fn md5_crypt(password: &str, salt: &str, magic: &str) -> String {
+    let pw = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+    let alt = Md5::new()
+        .chain_update(pw)
+        .chain_update(salt)
+        .chain_update(pw)
+        .finalize();
+    let mut ctx = Md5::new()
+        .chain_update(pw)
+        .chain_update(magic)
+        .chain_update(salt);
+    for i in (0..pw.len()).step_by(16) {
+        ctx.update(&alt[..(pw.len() - i).min(16)]);
+    }
+    let mut i = pw.len();
+    while i > 0 {
+        if i & 1 != 0 {
+            ctx.update([0]);
+        } else {
+            ctx.update(&pw[..1]);
+        }
+        i >>= 1;
+    }
+    let mut last = ctx.finalize();
+    for i in 0..1000 {
+        let mut ctx = Md5::new();
+        if i & 1 != 0 {
+            ctx.update(pw);
+        } else {
+            ctx.update(last);
+        }
+        if i % 3 != 0 {
+            ctx.update(salt);
+        }
+        if i % 7 != 0 {
+            ctx.update(pw);
+        }
+        if i & 1 != 0 {
+            ctx.update(last);
+        } else {
+            ctx.update(pw);
+        }
+        last = ctx.finalize();
+    }
+    // crypt's own base64, 3 bytes in a group, in this order
+    const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut out = format!("{}{}$", magic, String::from_utf8_lossy(salt));
+    let mut to64 = |mut v: u32, n: usize| {
+        for _ in 0..n {
+            out.push(ITOA64[(v & 0x3f) as usize] as char);
+            v >>= 6;
+        }
+    };
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        to64(
+            (u32::from(last[a]) << 16) | (u32::from(last[b]) << 8) | u32::from(last[c]),
+            4,
+        );
+    }
+    to64(u32::from(last[11]), 2);
+    out
+}
+
+fn md5_hex(s: &str) -> String {
+    Md5::digest(s.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// time of comparing secret don't tell how many bytes are right
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+// RandomState is seeded by OS random source, that is enough for a nonce key
+fn random_secret() -> String {
+    (0..2)
+        .map(|i| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(now() + i);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn auth(toml: &str, file: &str) -> Auth {
+    let mut auth: Auth = toml::from_str(toml).unwrap();
+    auth.load_file(file).unwrap();
+    auth.load().unwrap();
+    auth
+}
+
+#[cfg(test)]
+fn check(auth: &Auth, target: &str, authorization: &str) -> Result<String, Denied> {
+    let buf = format!(
+        "GET {} HTTP/1.1\r\nHost: a.test\r\nProxy-Authorization: {}\r\n\r\n",
+        target, authorization
+    );
+    auth.check(&Request::parse(buf.as_bytes()).unwrap())
+}
+
+#[test]
+fn htpasswd_hashes() {
+    assert_eq!(
+        md5_crypt("password", "abcdefgh", "$apr1$"),
+        "$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1"
+    );
+    assert_eq!(
+        md5_crypt("a very long password over 16 bytes", "abcdefgh", "$1$"),
+        "$1$abcdefgh$R0KVzb/DSlhztQbccKqUg1"
+    );
+    assert_eq!(
+        md5_crypt("", "xy", "$apr1$"),
+        "$apr1$xy$43..WIhbfuznGvwoCyUek/"
+    );
+    assert!(verify_password(
+        "password",
+        "$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1"
+    ));
+    assert!(!verify_password(
+        "Password",
+        "$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1"
+    ));
+    assert!(verify_password(
+        "secret",
+        "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ="
+    ));
+    let bcrypt = bcrypt::hash("secret", 4).unwrap();
+    assert!(verify_password("secret", &bcrypt));
+    assert!(!verify_password("secrets", &bcrypt));
+    assert!(!verify_password("secret", "secret"));
+}
+
+#[test]
+fn basic_auth() {
+    let auth = auth(
+        r#"
+        [[user]]
+        name = "alice"
+        password = "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ="
+        "#,
+        "# htpasswd\nbob:$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1\n",
+    );
+    let basic = |user_pass: &str| format!("Basic {}", BASE64.encode(user_pass));
+    assert_eq!(
+        check(&auth, "/", &basic("alice:secret")),
+        Ok("alice".to_owned())
+    );
+    assert_eq!(
+        check(&auth, "/", &basic("bob:password")),
+        Ok("bob".to_owned())
+    );
+    // verified credentials are remembered, wrong ones are not
+    assert_eq!(auth.verified.lock().unwrap().len(), 2);
+    assert_eq!(
+        check(&auth, "/", &basic("bob:password")),
+        Ok("bob".to_owned())
+    );
+    assert_eq!(
+        check(&auth, "/", &basic("bob:secret")),
+        Err(Denied::Invalid)
+    );
+    assert_eq!(
+        check(&auth, "/", &basic("eve:secret")),
+        Err(Denied::Invalid)
+    );
+    assert_eq!(check(&auth, "/", "Basic !!!"), Err(Denied::Invalid));
+    assert_eq!(auth.verified.lock().unwrap().len(), 2);
+    assert_eq!(
+        check(&auth, "/", "Digest username=\"alice\""),
+        Err(Denied::Invalid)
+    );
+    let buf = b"GET / HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(
+        auth.check(&Request::parse(buf).unwrap()),
+        Err(Denied::Missing)
+    );
+    assert_eq!(
+        auth.challenges(false),
+        ["Basic realm=\"proxy\", charset=\"UTF-8\""]
+    );
+}
+
+#[test]
+fn digest_rfc_example() {
+    // RFC 2617 section 3.5
+    let ha1 = md5_hex("Mufasa:testrealm@host.com:Circle Of Life");
+    let response = digest_response(
+        &ha1,
+        "dcd98b7102dd2f0e8b11d0f600bfb0c093",
+        Some(("00000001", "0a4f113b")),
+        "GET",
+        "/dir/index.html",
+    );
+    assert_eq!(response, "6629fae49393a05397450978507c4ef1");
+}
+
+#[test]
+fn digest_auth() {
+    let auth = auth(
+        r#"
+        scheme = ["digest", "basic"]
+        realm = "test"
+        "#,
+        "carol:test:0a0d0ffe1e3a2a5a3ab1b4ab2c1dd2a6\ncarol:other:00000000000000000000000000000000\n",
+    );
+    let ha1 = "0a0d0ffe1e3a2a5a3ab1b4ab2c1dd2a6";
+    let nonce = auth.nonce(now());
+    let header = |uri: &str, nonce: &str, response: &str| {
+        format!(
+            "Digest username=\"carol\", realm=\"test\", nonce=\"{}\", uri=\"{}\", \
+             qop=auth, nc=00000001, cnonce=\"xyz\", response=\"{}\"",
+            nonce, uri, response
+        )
+    };
+    let target = "http://a.test/x?y";
+    let response = digest_response(ha1, &nonce, Some(("00000001", "xyz")), "GET", target);
+    assert_eq!(
+        check(&auth, target, &header(target, &nonce, &response)),
+        Ok("carol".to_owned())
+    );
+    // origin-form of the same url
+    let origin = "/x?y";
+    let response = digest_response(ha1, &nonce, Some(("00000001", "xyz")), "GET", origin);
+    assert_eq!(
+        check(&auth, target, &header(origin, &nonce, &response)),
+        Ok("carol".to_owned())
+    );
+    // uri of another request
+    assert_eq!(
+        check(&auth, "http://a.test/z", &header(target, &nonce, &response)),
+        Err(Denied::Invalid)
+    );
+    // wrong response
+    let wrong = header(target, &nonce, "00000000000000000000000000000000");
+    assert_eq!(check(&auth, target, &wrong), Err(Denied::Invalid));
+    // old nonce
+    let old = auth.nonce(now() - NONCE_LIFETIME - 10);
+    let response = digest_response(ha1, &old, Some(("00000001", "xyz")), "GET", target);
+    assert_eq!(
+        check(&auth, target, &header(target, &old, &response)),
+        Err(Denied::Stale)
+    );
+    // forged nonce
+    let forged = format!("{:x}-00000000000000000000000000000000", now());
+    let response = digest_response(ha1, &forged, Some(("00000001", "xyz")), "GET", target);
+    assert_eq!(
+        check(&auth, target, &header(target, &forged, &response)),
+        Err(Denied::Invalid)
+    );
+    let challenges = auth.challenges(true);
+    assert!(challenges[0].starts_with("Digest realm=\"test\", qop=\"auth\""));
+    assert!(challenges[0].ends_with(", stale=true"));
+    assert!(challenges[1].starts_with("Basic"));
+}
+
+#[test]
+fn auth_params() {
+    let params = parse_params(r#"username="a\"b", qop=auth, nc=00000001 ,uri="/x,y""#);
+    assert_eq!(params["username"], "a\"b");
+    assert_eq!(params["qop"], "auth");
+    assert_eq!(params["nc"], "00000001");
+    assert_eq!(params["uri"], "/x,y");
+}
+
+#[test]
+fn auth_invalid_config() {
+    let load = |toml: &str| toml::from_str::<Auth>(toml).unwrap().load();
+    assert!(load("[[user]]\nname = \"a\"\npassword = \"plain\"").is_err());
+    assert!(load("[[user]]\nname = \"a\"\ndigest = \"xyz\"").is_err());
+    assert!(load("scheme = []").is_err());
+    assert!(
+        load("[[user]]\nname = \"a\"\npassword = \"{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\"").is_ok()
+    );
+}
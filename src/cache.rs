@@ -0,0 +1,892 @@
+use crate::disk::Disk;
+use crate::http::{parse_date, BodyLength, Headers, Request, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// heuristic freshness is 10% of time since Last-Modified, but at most a day
+const HEURISTIC_MAX: u64 = 86400;
+// first line of entry file, so that a file of another version is not read
+const MAGIC: &[u8] = b"PROXY CACHE 1\r\n";
+
+// shared cache of responses, see RFC 9111
+// [cache]
+// memory=67108864
+// max_object=1048576
+// dir="cache"
+// disk=1073741824
+#[derive(Deserialize)]
+pub struct Cache {
+    // bytes of responses kept in memory, least recently used ones are dropped
+    pub memory: u64,
+    // larger responses are not stored
+    #[serde(default = "default_max_object")]
+    pub max_object: u64,
+    // directory of responses kept after proxy restarts, only memory is used without it
+    pub dir: Option<String>,
+    // bytes of responses kept in directory
+    #[serde(default = "default_disk")]
+    pub disk: u64,
+    #[serde(skip)]
+    store: Mutex<Store>,
+    // dir above, opened when config is opened
+    #[serde(skip)]
+    files: Option<Mutex<Disk>>,
+}
+
+fn default_max_object() -> u64 {
+    1 << 20
+}
+
+fn default_disk() -> u64 {
+    1 << 30
+}
+
+#[derive(Default)]
+struct Store {
+    // "GET url" -> responses with different values of headers in Vary
+    entries: HashMap<String, Vec<Slot>>,
+    // last use -> key and id of entry, the first one is dropped when memory is full
+    lru: BTreeMap<u64, (String, u64)>,
+    // counter of uses, also gives id of entries
+    tick: u64,
+    // bytes of all entries
+    size: u64,
+}
+
+struct Slot {
+    entry: Arc<Entry>,
+    used: u64,
+}
+
+// a stored response
+#[derive(Clone)]
+pub struct Entry {
+    id: u64,
+    pub key: String,
+    // request headers named in Vary, and their values in the request
+    pub vary: Vec<(String, Option<Vec<u8>>)>,
+    // status line and headers, without hop-by-hop headers and length of body
+    pub head: Vec<u8>,
+    pub body: Vec<u8>,
+    // seconds since epoch when response came, and its age at that time
+    response_time: u64,
+    initial_age: u64,
+    // seconds it is fresh
+    lifetime: u64,
+}
+
+impl Entry {
+    pub fn age(&self, now: u64) -> u64 {
+        self.initial_age + now.saturating_sub(self.response_time)
+    }
+
+    fn size(&self) -> u64 {
+        (self.key.len() + self.head.len() + self.body.len()) as u64
+    }
+
+    // value of a stored header
+    pub fn header(&self, name: &str) -> Option<Vec<u8>> {
+        let res = Response::parse(&self.head).ok()?;
+        res.headers.get(name).map(<[u8]>::to_vec)
+    }
+
+    // stale response with ETag or Last-Modified can be used after server says so
+    fn has_validator(&self) -> bool {
+        self.header("ETag").is_some() || self.header("Last-Modified").is_some()
+    }
+
+    // content of entry file, it has
+    // magic line, key, "response_time initial_age lifetime head_len body_len",
+    // "name: value" of Vary or "name" if request doesn't have it, a blank line,
+    // then head and body
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        let meta = format!(
+            "{}\r\n{} {} {} {} {}\r\n",
+            self.key,
+            self.response_time,
+            self.initial_age,
+            self.lifetime,
+            self.head.len(),
+            self.body.len()
+        );
+        data.extend_from_slice(meta.as_bytes());
+        for (name, value) in &self.vary {
+            data.extend_from_slice(name.as_bytes());
+            if let Some(value) = value {
+                data.extend_from_slice(b": ");
+                data.extend_from_slice(value);
+            }
+            data.extend_from_slice(b"\r\n");
+        }
+        data.extend_from_slice(b"\r\n");
+        data.extend_from_slice(&self.head);
+        data.extend_from_slice(&self.body);
+        data
+    }
+
+    // read entry file written by encode(), body is not read if `with_body` is false
+    // `len` is size of the whole file, lengths in file are checked with it before reading
+    pub fn decode<R: BufRead>(reader: &mut R, len: u64, with_body: bool) -> io::Result<Entry> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "broken cache entry");
+        let mut line = Vec::new();
+        let mut size = reader.read_until(b'\n', &mut line)? as u64;
+        if line != MAGIC {
+            return Err(invalid());
+        }
+        let mut lines = Vec::new();
+        loop {
+            line.clear();
+            size += reader.read_until(b'\n', &mut line)? as u64;
+            if !line.ends_with(b"\r\n") {
+                return Err(invalid());
+            }
+            line.truncate(line.len() - 2);
+            if line.is_empty() && lines.len() >= 2 {
+                break;
+            }
+            lines.push(String::from_utf8(line.clone()).map_err(|_| invalid())?);
+        }
+        let numbers: Vec<u64> = lines[1]
+            .split(' ')
+            .map(|x| x.parse().ok())
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        let (response_time, initial_age, lifetime, head_len, body_len) = match numbers[..] {
+            [a, b, c, d, e] => (a, b, c, d, e),
+            _ => return Err(invalid()),
+        };
+        // broken file may give any length, don't allocate it
+        let rest = len.checked_sub(size).ok_or_else(invalid)?;
+        if head_len.checked_add(body_len) != Some(rest) {
+            return Err(invalid());
+        }
+        let vary = lines[2..]
+            .iter()
+            .map(|line| match line.find(": ") {
+                Some(pos) => (
+                    line[..pos].to_owned(),
+                    Some(line.as_bytes()[pos + 2..].to_vec()),
+                ),
+                None => (line.to_owned(), None),
+            })
+            .collect();
+        let mut head = vec![0; head_len as usize];
+        reader.read_exact(&mut head)?;
+        let mut body = Vec::new();
+        if with_body {
+            body.resize(body_len as usize, 0);
+            reader.read_exact(&mut body)?;
+        }
+        let entry = Entry {
+            id: 0,
+            key: lines.swap_remove(0),
+            vary,
+            head,
+            body,
+            response_time,
+            initial_age,
+            lifetime,
+        };
+        Ok(entry)
+    }
+}
+
+// directives of Cache-Control, "no-cache, max-age=60"
+pub struct Directives(Vec<(String, Option<String>)>);
+
+impl Directives {
+    pub fn of(headers: &Headers) -> Directives {
+        let mut list = Vec::new();
+        for value in headers.get_all("Cache-Control") {
+            for part in String::from_utf8_lossy(value).split(',') {
+                let part = part.trim();
+                let (name, arg) = match part.find('=') {
+                    Some(pos) => (&part[..pos], Some(part[pos + 1..].trim().trim_matches('"'))),
+                    None => (part, None),
+                };
+                if !name.is_empty() {
+                    list.push((name.trim().to_ascii_lowercase(), arg.map(str::to_owned)));
+                }
+            }
+        }
+        Directives(list)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.0.iter().any(|(x, _)| x == name)
+    }
+
+    // seconds of "max-age=60", None if it is not given or not a number
+    pub fn seconds(&self, name: &str) -> Option<u64> {
+        self.0
+            .iter()
+            .find(|(x, _)| x == name)
+            .and_then(|(_, arg)| arg.as_ref()?.parse().ok())
+    }
+}
+
+// what cache has for a request
+pub enum Lookup {
+    // fresh response, send it to client
+    Hit(Arc<Entry>),
+    // response that must be revalidated with server before it is sent
+    Stale(Arc<Entry>),
+    Miss,
+}
+
+// request that may be answered by cache
+pub fn cacheable(req: &Request, length: BodyLength) -> bool {
+    (req.method == "GET" || req.method == "HEAD")
+        && length == BodyLength::Empty
+        && !req.headers.contains("Range")
+}
+
+// request asks for a response that is not from cache
+fn no_cache(req: &Request, directives: &Directives) -> bool {
+    directives.has("no-cache")
+        || !req.headers.contains("Cache-Control")
+            && req
+                .headers
+                .get_all("Pragma")
+                .any(|x| x.eq_ignore_ascii_case(b"no-cache"))
+}
+
+// response of HEAD is the same as GET without body
+fn key(req: &Request) -> String {
+    format!("GET {}", req.url())
+}
+
+// whether response of request is stored, and seconds it is fresh
+// response with ETag or Last-Modified is stored even if it is never fresh
+// see RFC 9111 section 3 and 4.2
+pub fn lifetime(req: &Request, res: &Response, response_time: u64) -> Option<u64> {
+    if req.method != "GET" {
+        return None;
+    }
+    let request = Directives::of(&req.headers);
+    let directives = Directives::of(&res.headers);
+    // proxy is a shared cache, response for one user is private
+    if request.has("no-store")
+        || directives.has("no-store")
+        || directives.has("private")
+        || res.headers.get_all("Vary").any(|x| x.contains(&b'*'))
+    {
+        return None;
+    }
+    let authorized = ["public", "s-maxage", "must-revalidate"];
+    if req.headers.contains("Authorization") && !authorized.iter().any(|x| directives.has(x)) {
+        return None;
+    }
+    let date = res
+        .headers
+        .get("Date")
+        .and_then(parse_date)
+        .unwrap_or(response_time);
+    let explicit = directives
+        .seconds("s-maxage")
+        .or_else(|| directives.seconds("max-age"))
+        .or_else(|| {
+            // invalid Expires, like "0", means it has expired
+            let expires = res.headers.get("Expires")?;
+            Some(parse_date(expires).map_or(0, |expires| expires.saturating_sub(date)))
+        });
+    let lifetime = match explicit {
+        Some(lifetime) if understood(res.code) => lifetime,
+        Some(_) => return None,
+        None if heuristic(res.code) => {
+            match res.headers.get("Last-Modified").and_then(parse_date) {
+                Some(modified) => std::cmp::min(date.saturating_sub(modified) / 10, HEURISTIC_MAX),
+                None => 0,
+            }
+        }
+        None => return None,
+    };
+    // "no-cache" response must be revalidated every time
+    let lifetime = if directives.has("no-cache") {
+        0
+    } else {
+        lifetime
+    };
+    let validator = res.headers.contains("ETag") || res.headers.contains("Last-Modified");
+    if lifetime == 0 && !validator {
+        return None;
+    }
+    Some(lifetime)
+}
+
+// status codes that can be stored with an explicit lifetime
+fn understood(code: u16) -> bool {
+    heuristic(code) || code == 302 || code == 307
+}
+
+// status codes that are fresh for a while without Cache-Control or Expires
+fn heuristic(code: u16) -> bool {
+    matches!(
+        code,
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+impl Cache {
+    // response of request, and whether it can be sent without asking server
+    pub fn lookup(&self, req: &Request, now: u64) -> Lookup {
+        let directives = Directives::of(&req.headers);
+        if directives.has("no-store") {
+            return Lookup::Miss;
+        }
+        let entry = match self.find(req) {
+            Some(entry) => entry,
+            None => return Lookup::Miss,
+        };
+        let age = entry.age(now);
+        // client may want a newer one, or accept an older one
+        let fresh = entry.lifetime > age + directives.seconds("min-fresh").unwrap_or(0);
+        let stale = match directives.seconds("max-stale") {
+            Some(stale) => entry.lifetime + stale > age,
+            None => directives.has("max-stale"),
+        };
+        let usable = (fresh || stale && !must_revalidate(&entry))
+            && !no_cache(req, &directives)
+            && directives.seconds("max-age").is_none_or(|max| age <= max);
+        if usable {
+            Lookup::Hit(entry)
+        } else if entry.has_validator() {
+            Lookup::Stale(entry)
+        } else {
+            Lookup::Miss
+        }
+    }
+
+    // response with the same Vary values, in memory or on disk
+    fn find(&self, req: &Request) -> Option<Arc<Entry>> {
+        let key = key(req);
+        {
+            let mut store = self.store.lock().unwrap();
+            let found = store.entries.get(&key).and_then(|slots| {
+                slots
+                    .iter()
+                    .find(|slot| vary_match(&slot.entry.vary, &req.headers))
+                    .map(|slot| Arc::clone(&slot.entry))
+            });
+            if let Some(entry) = found {
+                store.touch(&entry);
+                return Some(entry);
+            }
+        }
+        // file is read without lock, other threads don't wait for it
+        let (dir, files) = (self.dir.as_ref()?, self.files.as_ref()?);
+        let name = files.lock().unwrap().find(&key, &req.headers)?;
+        let read = Disk::read(dir, &name);
+        let entry = files.lock().unwrap().loaded(&name, read)?;
+        // it will be used again soon, keep it in memory
+        Some(self.remember(entry))
+    }
+
+    // response for the cache, body is added by store()
+    // head should not have hop-by-hop headers
+    pub fn entry(
+        &self,
+        req: &Request,
+        res: &Response,
+        request_time: u64,
+        response_time: u64,
+    ) -> Option<Entry> {
+        let lifetime = lifetime(req, res, response_time)?;
+        // Age and length of body are set when it is sent
+        let mut head = format!("{} {} {}\r\n", res.version, res.code, res.reason).into_bytes();
+        for header in res.headers.iter() {
+            let skipped = ["Age", "Content-Length", "Transfer-Encoding"];
+            if !skipped.iter().any(|x| x.eq_ignore_ascii_case(header.key())) {
+                head.extend_from_slice(header.key().as_bytes());
+                head.extend_from_slice(b": ");
+                head.extend_from_slice(header.value());
+                head.extend_from_slice(b"\r\n");
+            }
+        }
+        head.extend_from_slice(b"\r\n");
+        let vary = res
+            .headers
+            .get_all("Vary")
+            .flat_map(|value| value.split(|&c| c == b','))
+            .filter_map(|name| std::str::from_utf8(name).ok())
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = join(&req.headers, &name);
+                (name, value)
+            })
+            .collect();
+        // see RFC 9111 section 4.2.3
+        let date = res
+            .headers
+            .get("Date")
+            .and_then(parse_date)
+            .unwrap_or(response_time);
+        let age = res
+            .headers
+            .get("Age")
+            .and_then(|x| std::str::from_utf8(x).ok()?.trim().parse().ok())
+            .unwrap_or(0);
+        let apparent_age = response_time.saturating_sub(date);
+        let corrected_age = age + response_time.saturating_sub(request_time);
+        Some(Entry {
+            id: 0,
+            key: key(req),
+            vary,
+            head,
+            body: Vec::new(),
+            response_time,
+            initial_age: std::cmp::max(apparent_age, corrected_age),
+            lifetime,
+        })
+    }
+
+    // server says stale response is not modified with a 304 response
+    // headers in 304 replace stored ones, see RFC 9111 section 4.3.4
+    pub fn refresh(
+        &self,
+        req: &Request,
+        entry: &Entry,
+        res: &Response,
+        request_time: u64,
+        response_time: u64,
+    ) -> Arc<Entry> {
+        let mut stored = match Response::parse(&entry.head) {
+            Ok(stored) => stored,
+            Err(_) => return Arc::new(entry.clone()),
+        };
+        let skipped = ["Content-Length", "Transfer-Encoding", "Content-Encoding"];
+        let updated = || {
+            res.headers
+                .iter()
+                .filter(|header| !skipped.iter().any(|x| x.eq_ignore_ascii_case(header.key())))
+        };
+        // Set-Cookie, Vary or Link may be repeated, all values of a name replace old ones
+        for header in updated() {
+            stored.headers.remove(header.key());
+        }
+        for header in updated() {
+            stored
+                .headers
+                .append(header.key().to_owned(), header.value().to_vec());
+        }
+        // Age of the 304 is the age of stored response now
+        if !res.headers.contains("Age") {
+            stored.headers.remove("Age");
+        }
+        // stored response is of GET, even if HEAD asks server about it
+        let mut get = req.clone();
+        get.method = "GET";
+        match self.entry(&get, &stored, request_time, response_time) {
+            Some(refreshed) => self.store(refreshed, entry.body.clone()),
+            // server doesn't let us keep it any more, send it this time only
+            None => {
+                self.invalidate(&get);
+                Arc::new(entry.clone())
+            }
+        }
+    }
+
+    // keep a response, it replaces the old one with the same Vary values
+    pub fn store(&self, mut entry: Entry, body: Vec<u8>) -> Arc<Entry> {
+        entry.body = body;
+        if entry.size() > self.max_object {
+            return Arc::new(entry);
+        }
+        if let (Some(dir), Some(files)) = (&self.dir, &self.files) {
+            // file is written and synced without lock, other threads don't wait for it
+            let saved = Disk::write(dir, &entry)
+                .and_then(|written| files.lock().unwrap().commit(&entry, written));
+            if let Err(e) = saved {
+                warn!("failed to write {} to cache directory, {}", entry.key, e);
+            }
+        }
+        self.remember(entry)
+    }
+
+    // keep a response in memory
+    fn remember(&self, mut entry: Entry) -> Arc<Entry> {
+        let size = entry.size();
+        if size > self.memory {
+            return Arc::new(entry);
+        }
+        let mut store = self.store.lock().unwrap();
+        store.tick += 1;
+        entry.id = store.tick;
+        store.remove(&entry.key, |slot| slot.entry.vary == entry.vary);
+        while store.size + size > self.memory {
+            match store.lru.keys().next().cloned() {
+                Some(oldest) => {
+                    let (key, id) = store.lru[&oldest].clone();
+                    store.remove(&key, |slot| slot.entry.id == id);
+                }
+                None => break,
+            }
+        }
+        let used = store.tick;
+        store.lru.insert(used, (entry.key.clone(), entry.id));
+        store.size += size;
+        let entry = Arc::new(entry);
+        let slot = Slot {
+            entry: Arc::clone(&entry),
+            used,
+        };
+        store
+            .entries
+            .entry(entry.key.clone())
+            .or_default()
+            .push(slot);
+        entry
+    }
+
+    // unsafe method like POST changes resource, stored responses of it are old
+    // see RFC 9111 section 4.4
+    pub fn invalidate(&self, req: &Request) {
+        let key = key(req);
+        self.store.lock().unwrap().remove(&key, |_| true);
+        if let Some(files) = &self.files {
+            files.lock().unwrap().remove_key(&key);
+        }
+    }
+
+    // open cache directory and recover entries in it
+    pub fn load(&mut self) -> io::Result<()> {
+        if let Some(dir) = &self.dir {
+            let disk = Disk::open(dir, self.disk)
+                .map_err(|e| io::Error::new(e.kind(), format!("cache {}: {}", dir, e)))?;
+            self.files = Some(Mutex::new(disk));
+        }
+        Ok(())
+    }
+
+    // entries found in cache directory and broken ones removed when it is opened
+    pub fn recovered(&self) -> Option<(usize, usize)> {
+        let disk = self.files.as_ref()?.lock().unwrap();
+        Some((disk.recovered, disk.discarded))
+    }
+}
+
+impl Store {
+    fn touch(&mut self, entry: &Entry) {
+        self.tick += 1;
+        let tick = self.tick;
+        let slots = match self.entries.get_mut(&entry.key) {
+            Some(slots) => slots,
+            None => return,
+        };
+        if let Some(slot) = slots.iter_mut().find(|slot| slot.entry.id == entry.id) {
+            let old = std::mem::replace(&mut slot.used, tick);
+            if let Some(value) = self.lru.remove(&old) {
+                self.lru.insert(tick, value);
+            }
+        }
+    }
+
+    fn remove<F>(&mut self, key: &str, mut f: F)
+    where
+        F: FnMut(&Slot) -> bool,
+    {
+        let slots = match self.entries.get_mut(key) {
+            Some(slots) => slots,
+            None => return,
+        };
+        let (lru, size) = (&mut self.lru, &mut self.size);
+        slots.retain(|slot| {
+            if !f(slot) {
+                return true;
+            }
+            lru.remove(&slot.used);
+            *size -= slot.entry.size();
+            false
+        });
+        if slots.is_empty() {
+            self.entries.remove(key);
+        }
+    }
+}
+
+// stale response that can't be sent even if client accepts it
+// shared cache must revalidate no-cache and s-maxage too, see RFC 9111 section 5.2.2
+fn must_revalidate(entry: &Entry) -> bool {
+    let directives = match Response::parse(&entry.head) {
+        Ok(res) => Directives::of(&res.headers),
+        Err(_) => return true,
+    };
+    [
+        "must-revalidate",
+        "proxy-revalidate",
+        "no-cache",
+        "s-maxage",
+    ]
+    .iter()
+    .any(|x| directives.has(x))
+}
+
+// all values of a header in one, "a, b"
+fn join(headers: &Headers, name: &str) -> Option<Vec<u8>> {
+    let mut values = headers.get_all(name);
+    let mut joined = values.next()?.to_vec();
+    for value in values {
+        joined.extend_from_slice(b", ");
+        joined.extend_from_slice(value);
+    }
+    Some(joined)
+}
+
+pub fn vary_match(vary: &[(String, Option<Vec<u8>>)], headers: &Headers) -> bool {
+    vary.iter()
+        .all(|(name, value)| join(headers, name) == *value)
+}
+
+// seconds since epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn test_cache(memory: u64) -> Cache {
+    toml::from_str(&format!("memory = {}", memory)).unwrap()
+}
+
+#[cfg(test)]
+fn test_store(cache: &Cache, req: &str, res: &str, body: &str, now: u64) {
+    let req = Request::parse(req.as_bytes()).unwrap();
+    let res = Response::parse(res.as_bytes()).unwrap();
+    let entry = cache.entry(&req, &res, now, now).unwrap();
+    cache.store(entry, body.as_bytes().to_vec());
+}
+
+#[cfg(test)]
+fn test_lookup(cache: &Cache, req: &str, now: u64) -> Option<String> {
+    let req = Request::parse(req.as_bytes()).unwrap();
+    match cache.lookup(&req, now) {
+        Lookup::Hit(entry) => Some(String::from_utf8(entry.body.clone()).unwrap()),
+        _ => None,
+    }
+}
+
+#[test]
+fn cache_control_directives() {
+    let res = Response::parse(
+        b"HTTP/1.1 200 OK\r\nCache-Control: public, max-age=\"60\"\r\n\
+          Cache-Control: No-Cache\r\n\r\n",
+    )
+    .unwrap();
+    let directives = Directives::of(&res.headers);
+    assert!(directives.has("public") && directives.has("no-cache"));
+    assert_eq!(directives.seconds("max-age"), Some(60));
+    assert_eq!(directives.seconds("s-maxage"), None);
+}
+
+#[test]
+fn freshness_lifetime() {
+    let get = Request::parse(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+    let lifetime = |res: &str| {
+        let res = Response::parse(res.as_bytes()).unwrap();
+        lifetime(&get, &res, 784111777)
+    };
+    let date = "Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+    let ok = |headers: &str| lifetime(&format!("HTTP/1.1 200 OK\r\n{}{}\r\n", date, headers));
+    assert_eq!(ok("Cache-Control: max-age=60, s-maxage=30\r\n"), Some(30));
+    assert_eq!(ok("Cache-Control: max-age=60\r\nExpires: 0\r\n"), Some(60));
+    assert_eq!(ok("Expires: Sun, 06 Nov 1994 08:59:37 GMT\r\n"), Some(600));
+    assert_eq!(ok("Expires: 0\r\n"), None);
+    assert_eq!(
+        ok("Last-Modified: Sun, 06 Nov 1994 07:49:37 GMT\r\n"),
+        Some(360)
+    );
+    assert_eq!(
+        ok("Last-Modified: Sun, 06 Nov 1984 07:49:37 GMT\r\n"),
+        Some(HEURISTIC_MAX)
+    );
+    assert_eq!(ok(""), None);
+    assert_eq!(ok("Cache-Control: max-age=60, private\r\n"), None);
+    assert_eq!(ok("Cache-Control: max-age=60, no-store\r\n"), None);
+    assert_eq!(ok("Cache-Control: max-age=60\r\nVary: *\r\n"), None);
+    // no-cache is kept only if it can be revalidated
+    assert_eq!(ok("Cache-Control: no-cache\r\n"), None);
+    assert_eq!(
+        ok("Cache-Control: max-age=60, no-cache\r\nETag: \"1\"\r\n"),
+        Some(0)
+    );
+    assert_eq!(ok("Cache-Control: max-age=0\r\nETag: \"1\"\r\n"), Some(0));
+    let res = "Cache-Control: max-age=60\r\nLast-Modified: Sun, 06 Nov 1994 07:49:37 GMT\r\n";
+    assert_eq!(
+        lifetime(&format!("HTTP/1.1 302 Found\r\n{}\r\n", res)),
+        Some(60)
+    );
+    let res = "Last-Modified: Sun, 06 Nov 1994 07:49:37 GMT\r\n";
+    assert_eq!(
+        lifetime(&format!("HTTP/1.1 302 Found\r\n{}\r\n", res)),
+        None
+    );
+    assert_eq!(
+        lifetime("HTTP/1.1 500 Error\r\nCache-Control: max-age=60\r\n\r\n"),
+        None
+    );
+}
+
+#[test]
+fn cache_hit_and_vary() {
+    let cache = test_cache(1 << 20);
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept-Encoding\r\n\r\n";
+    let plain = "GET http://a.test/x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    let gzip = "GET /x HTTP/1.1\r\nHost: a.test\r\nAccept-Encoding: gzip\r\n\r\n";
+    test_store(&cache, plain, res, "plain", 1000);
+    test_store(&cache, gzip, res, "gzip", 1000);
+    assert_eq!(test_lookup(&cache, plain, 1030).as_deref(), Some("plain"));
+    assert_eq!(test_lookup(&cache, gzip, 1030).as_deref(), Some("gzip"));
+    let head = "HEAD /x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(test_lookup(&cache, head, 1030).as_deref(), Some("plain"));
+    let br = "GET /x HTTP/1.1\r\nHost: a.test\r\nAccept-Encoding: br\r\n\r\n";
+    assert_eq!(test_lookup(&cache, br, 1030), None);
+    // stale
+    assert_eq!(test_lookup(&cache, plain, 1060), None);
+    // new response replaces old one
+    test_store(&cache, plain, res, "new", 1000);
+    assert_eq!(test_lookup(&cache, plain, 1030).as_deref(), Some("new"));
+    let post = Request::parse(b"POST /x HTTP/1.1\r\nHost: a.test\r\n\r\n").unwrap();
+    cache.invalidate(&post);
+    assert_eq!(test_lookup(&cache, gzip, 1030), None);
+    assert_eq!(cache.store.lock().unwrap().size, 0);
+}
+
+#[test]
+fn cache_request_directives() {
+    let cache = test_cache(1 << 20);
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nAge: 10\r\n\r\n";
+    let req = |headers: &str| format!("GET /x HTTP/1.1\r\nHost: a.test\r\n{}\r\n", headers);
+    test_store(&cache, &req(""), res, "x", 1000);
+    // age is 10 + 20
+    assert!(test_lookup(&cache, &req(""), 1020).is_some());
+    assert!(test_lookup(&cache, &req("Cache-Control: no-cache\r\n"), 1020).is_none());
+    assert!(test_lookup(&cache, &req("Pragma: no-cache\r\n"), 1020).is_none());
+    assert!(test_lookup(&cache, &req("Cache-Control: max-age=20\r\n"), 1020).is_none());
+    assert!(test_lookup(&cache, &req("Cache-Control: max-age=40\r\n"), 1020).is_some());
+    assert!(test_lookup(&cache, &req("Cache-Control: min-fresh=40\r\n"), 1020).is_none());
+    assert!(test_lookup(&cache, &req("Cache-Control: min-fresh=20\r\n"), 1020).is_some());
+    assert!(test_lookup(&cache, &req(""), 1060).is_none());
+    assert!(test_lookup(&cache, &req("Cache-Control: max-stale=30\r\n"), 1060).is_some());
+    assert!(test_lookup(&cache, &req("Cache-Control: max-stale\r\n"), 9000).is_some());
+    // no-store in request is not stored
+    let no_store = req("Cache-Control: no-store\r\n");
+    let no_store = Request::parse(no_store.as_bytes()).unwrap();
+    let res = Response::parse(res.as_bytes()).unwrap();
+    assert!(cache.entry(&no_store, &res, 1000, 1000).is_none());
+}
+
+#[test]
+fn cache_revalidate() {
+    let cache = test_cache(1 << 20);
+    let get = "GET /x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nETag: \"1\"\r\nX-A: 1\r\n\
+               Link: <a>\r\nLink: <b>\r\nX-B: 1\r\n\r\n";
+    test_store(&cache, get, res, "body", 1000);
+    let req = Request::parse(get.as_bytes()).unwrap();
+    let entry = match cache.lookup(&req, 1060) {
+        Lookup::Stale(entry) => entry,
+        _ => panic!("entry is not stale"),
+    };
+    assert_eq!(entry.header("ETag").as_deref(), Some(&b"\"1\""[..]));
+    // 304 updates headers and freshness, body is kept
+    let not_modified = "HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=30\r\nX-A: 2\r\n\
+                        Link: <c>\r\nLink: <d>\r\n\r\n";
+    let not_modified = Response::parse(not_modified.as_bytes()).unwrap();
+    let refreshed = cache.refresh(&req, &entry, &not_modified, 1060, 1060);
+    assert_eq!(refreshed.body, b"body");
+    assert_eq!(refreshed.header("X-A").as_deref(), Some(&b"2"[..]));
+    // every value of a repeated header is kept, headers not in 304 are not changed
+    let head = Response::parse(&refreshed.head).unwrap();
+    let links: Vec<&[u8]> = head.headers.get_all("Link").collect();
+    assert_eq!(links, [&b"<c>"[..], &b"<d>"[..]]);
+    assert_eq!(head.headers.get("X-B"), Some(&b"1"[..]));
+    // client that has the same response gets 304 from proxy
+    let client = "GET /x HTTP/1.1\r\nHost: a.test\r\nIf-None-Match: \"1\"\r\n\r\n";
+    let client = Request::parse(client.as_bytes()).unwrap();
+    assert!(client.not_modified(head.headers.get("ETag"), head.headers.get("Last-Modified")));
+    assert_eq!(test_lookup(&cache, get, 1080).as_deref(), Some("body"));
+    assert_eq!(test_lookup(&cache, get, 1090), None);
+    // without a validator it can't be revalidated
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\n";
+    test_store(&cache, get, res, "body", 1000);
+    assert!(matches!(cache.lookup(&req, 1060), Lookup::Miss));
+    // must-revalidate can't be sent stale, even with max-stale
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60, must-revalidate\r\n\
+               ETag: \"1\"\r\n\r\n";
+    test_store(&cache, get, res, "body", 1000);
+    let stale = "GET /x HTTP/1.1\r\nHost: a.test\r\nCache-Control: max-stale\r\n\r\n";
+    let stale = Request::parse(stale.as_bytes()).unwrap();
+    assert!(matches!(cache.lookup(&stale, 1060), Lookup::Stale(_)));
+}
+
+#[test]
+fn cache_stale_must_revalidate() {
+    let cache = test_cache(1 << 20);
+    let get = "GET /x HTTP/1.1\r\nHost: a.test\r\nCache-Control: max-stale\r\n\r\n";
+    let req = Request::parse(get.as_bytes()).unwrap();
+    for directive in &["no-cache", "proxy-revalidate, max-age=60", "s-maxage=60"] {
+        let res = format!(
+            "HTTP/1.1 200 OK\r\nCache-Control: {}\r\nETag: \"1\"\r\n\r\n",
+            directive
+        );
+        test_store(&cache, get, &res, "body", 1000);
+        assert!(
+            matches!(cache.lookup(&req, 1100), Lookup::Stale(_)),
+            "{}",
+            directive
+        );
+    }
+    // other responses can be sent stale if client accepts it
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nETag: \"1\"\r\n\r\n";
+    test_store(&cache, get, res, "body", 1000);
+    assert!(matches!(cache.lookup(&req, 1100), Lookup::Hit(_)));
+}
+
+#[test]
+fn cache_revalidate_head() {
+    let cache = test_cache(1 << 20);
+    let get = "GET /x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nETag: \"1\"\r\n\r\n";
+    test_store(&cache, get, res, "body", 1000);
+    // HEAD asks server about the stored GET response
+    let head = Request::parse(b"HEAD /x HTTP/1.1\r\nHost: a.test\r\n\r\n").unwrap();
+    let entry = match cache.lookup(&head, 1060) {
+        Lookup::Stale(entry) => entry,
+        _ => panic!("entry is not stale"),
+    };
+    let not_modified = "HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=60\r\n\r\n";
+    let not_modified = Response::parse(not_modified.as_bytes()).unwrap();
+    let refreshed = cache.refresh(&head, &entry, &not_modified, 1060, 1060);
+    assert_eq!(refreshed.body, b"body");
+    assert_eq!(test_lookup(&cache, get, 1070).as_deref(), Some("body"));
+}
+
+#[test]
+fn cache_lru() {
+    let res = "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\n";
+    let req = |path: &str| format!("GET {} HTTP/1.1\r\nHost: a.test\r\n\r\n", path);
+    let body = "x".repeat(100);
+    // three entries fit in memory, key is "GET http://a.test/1"
+    let cache = test_cache(3 * (19 + res.len() as u64 + 100));
+    test_store(&cache, &req("/1"), res, &body, 1000);
+    test_store(&cache, &req("/2"), res, &body, 1000);
+    test_store(&cache, &req("/3"), res, &body, 1000);
+    assert!(test_lookup(&cache, &req("/1"), 1000).is_some());
+    test_store(&cache, &req("/4"), res, &body, 1000);
+    assert!(test_lookup(&cache, &req("/2"), 1000).is_none());
+    assert!(test_lookup(&cache, &req("/1"), 1000).is_some());
+    assert!(test_lookup(&cache, &req("/3"), 1000).is_some());
+    assert!(test_lookup(&cache, &req("/4"), 1000).is_some());
+    // too large for cache
+    test_store(&cache, &req("/5"), res, &"x".repeat(2 << 20), 1000);
+    assert!(test_lookup(&cache, &req("/5"), 1000).is_none());
+    assert!(test_lookup(&cache, &req("/4"), 1000).is_some());
+}
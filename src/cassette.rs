@@ -0,0 +1,272 @@
+use crate::http::{copy_body_inspect, read_head, BodyLength, Headers, Request, Response};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::sync::Mutex;
+
+// exchanges with servers kept in a file, so that tests can run without network
+// [cassette]
+// mode="record"
+// path="cassette.http"
+// status=502
+// file is requests and responses one after another, like they are on a connection,
+// every body has Content-Length, so the file can be read and edited by hand
+#[derive(Deserialize)]
+pub struct Cassette {
+    // "record" asks servers and writes exchanges, "replay" only reads them
+    pub mode: Mode,
+    pub path: String,
+    // status code sent for request not in cassette when replaying
+    #[serde(default = "default_status")]
+    pub status: u16,
+    // file written when recording, created when config is opened
+    #[serde(skip)]
+    file: Option<Mutex<File>>,
+    // responses read when replaying, the same request gets them in order
+    #[serde(skip)]
+    tapes: Mutex<HashMap<Key, VecDeque<Vec<u8>>>>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+fn default_status() -> u16 {
+    502
+}
+
+// "GET url" and body of request
+type Key = (String, Vec<u8>);
+
+fn key(req: &Request, body: &[u8]) -> Key {
+    (format!("{} {}", req.method, req.url()), body.to_vec())
+}
+
+impl Cassette {
+    pub fn load(&mut self) -> io::Result<()> {
+        if !(400..600).contains(&self.status) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("cassette status {} is not an error", self.status),
+            ));
+        }
+        let path = self.path.clone();
+        let error = |e: io::Error| io::Error::new(e.kind(), format!("cassette {}: {}", path, e));
+        match self.mode {
+            // every recording starts with an empty cassette
+            Mode::Record => self.file = Some(Mutex::new(File::create(&path).map_err(error)?)),
+            Mode::Replay => {
+                let mut reader = BufReader::new(File::open(&path).map_err(error)?);
+                self.tapes = Mutex::new(read(&mut reader).map_err(error)?);
+            }
+        }
+        Ok(())
+    }
+
+    // number of recorded responses when replaying
+    pub fn recorded(&self) -> usize {
+        self.tapes
+            .lock()
+            .unwrap()
+            .values()
+            .map(|tape| tape.len())
+            .sum()
+    }
+
+    // write an exchange, `res_head` is the response from server without its body
+    // bodies are decoded ones
+    pub fn record(
+        &self,
+        req: &Request,
+        req_body: &[u8],
+        res_head: &[u8],
+        length: BodyLength,
+        res_body: &[u8],
+    ) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let mut buf = Vec::new();
+        let mut req = req.clone();
+        let req_length = req.body_length().unwrap_or(BodyLength::Empty);
+        set_length(&mut req.headers, req_length, req_body);
+        req.body = req_body;
+        let written = req
+            .write(&mut buf)
+            .and_then(|_| write_response(&mut buf, res_head, length, res_body));
+        // a broken exchange is not written, or the rest of file can't be read
+        if let Err(e) = written {
+            warn!("failed to record {} in cassette, {}", req.url(), e);
+            return;
+        }
+        if let Err(e) = file.lock().unwrap().write_all(&buf) {
+            warn!("failed to write cassette {}, {}", self.path, e);
+        }
+    }
+
+    // recorded response of request, with its body
+    // the same request gets responses in recorded order, the last one is repeated
+    pub fn replay(&self, req: &Request, body: &[u8]) -> Option<Vec<u8>> {
+        let mut tapes = self.tapes.lock().unwrap();
+        let tape = tapes.get_mut(&key(req, body))?;
+        if tape.len() > 1 {
+            tape.pop_front()
+        } else {
+            tape.front().cloned()
+        }
+    }
+}
+
+// body is decoded, so length of chunked body or body until close is given
+fn set_length(headers: &mut Headers, length: BodyLength, body: &[u8]) {
+    if let BodyLength::Chunked | BodyLength::Close = length {
+        headers.remove("Transfer-Encoding");
+        headers.insert("Content-Length", body.len().to_string());
+    }
+}
+
+fn write_response(
+    buf: &mut Vec<u8>,
+    head: &[u8],
+    length: BodyLength,
+    body: &[u8],
+) -> io::Result<()> {
+    let mut res = Response::parse(head)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    set_length(&mut res.headers, length, body);
+    res.body = body;
+    res.write(buf)
+}
+
+// read exchanges of a cassette
+fn read<R: BufRead>(reader: &mut R) -> io::Result<HashMap<Key, VecDeque<Vec<u8>>>> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, format!("{}", e));
+    let mut tapes: HashMap<Key, VecDeque<Vec<u8>>> = HashMap::new();
+    let (mut req_head, mut res_head) = (Vec::new(), Vec::new());
+    while read_head(reader, &mut req_head)? > 0 {
+        let req = Request::parse(&req_head).map_err(invalid)?;
+        let req_body = read_body(reader, req.body_length().map_err(invalid)?)?;
+        if read_head(reader, &mut res_head)? == 0 {
+            let e = format!("no response of {} {}", req.method, req.url());
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, e));
+        }
+        let length = Response::parse(&res_head)
+            .and_then(|res| res.body_length(req.method))
+            .map_err(invalid)?;
+        let res_body = read_body(reader, length)?;
+        let mut res = Vec::new();
+        write_response(&mut res, &res_head, length, &res_body)?;
+        tapes
+            .entry(key(&req, &req_body))
+            .or_default()
+            .push_back(res);
+    }
+    Ok(tapes)
+}
+
+// decoded body of a message
+pub fn read_body<R: BufRead>(reader: &mut R, length: BodyLength) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    copy_body_inspect(reader, &mut io::sink(), length, |data| {
+        body.extend_from_slice(data)
+    })?;
+    Ok(body)
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn test_cassette(mode: &str, path: &str) -> Cassette {
+    let mut cassette: Cassette =
+        toml::from_str(&format!("mode = {:?}\npath = {:?}", mode, path)).unwrap();
+    cassette.load().unwrap();
+    cassette
+}
+
+#[cfg(test)]
+fn test_replay(cassette: &Cassette, req: &str) -> Option<String> {
+    let req = Request::parse(req.as_bytes()).unwrap();
+    let res = cassette.replay(&req, req.body)?;
+    Some(String::from_utf8(res).unwrap())
+}
+
+#[test]
+fn cassette_record_replay() {
+    let path = std::env::temp_dir().join(format!("proxy-cassette-{}", std::process::id()));
+    let path = path.to_string_lossy().into_owned();
+    let recorder = test_cassette("record", &path);
+    let record = |req: &str, body: &str, res: &str, res_body: &str| {
+        let req = Request::parse(req.as_bytes()).unwrap();
+        let res_head = Response::parse(res.as_bytes()).unwrap();
+        let length = res_head.body_length(req.method).unwrap();
+        recorder.record(
+            &req,
+            body.as_bytes(),
+            res.as_bytes(),
+            length,
+            res_body.as_bytes(),
+        );
+    };
+    let get = "GET http://a.test/x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    record(
+        get,
+        "",
+        "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n",
+        "one",
+    );
+    record(
+        get,
+        "",
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+        "two",
+    );
+    let post = "POST /x HTTP/1.1\r\nHost: a.test\r\nTransfer-Encoding: chunked\r\n\r\n";
+    record(post, "a=1", "HTTP/1.1 201 Created\r\n\r\n", "created");
+    let head = "HEAD /x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    record(
+        head,
+        "",
+        "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n",
+        "",
+    );
+    drop(recorder);
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.contains("POST /x HTTP/1.1\r\nHost: a.test\r\nContent-Length: 3\r\n\r\na=1"));
+
+    let player = test_cassette("replay", &path);
+    assert_eq!(player.recorded(), 4);
+    let one = "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\none";
+    let two = "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\ntwo";
+    // origin-form and absolute-form are the same request
+    let origin = "GET /x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(test_replay(&player, origin).as_deref(), Some(one));
+    assert_eq!(test_replay(&player, get).as_deref(), Some(two));
+    assert_eq!(test_replay(&player, get).as_deref(), Some(two));
+    let created = "HTTP/1.1 201 Created\r\nContent-Length: 7\r\n\r\ncreated";
+    let post = "POST /x HTTP/1.1\r\nHost: a.test\r\nContent-Length: 3\r\n\r\na=1";
+    assert_eq!(test_replay(&player, post).as_deref(), Some(created));
+    let other = "POST /x HTTP/1.1\r\nHost: a.test\r\nContent-Length: 3\r\n\r\na=2";
+    assert_eq!(test_replay(&player, other), None);
+    let head_res = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n";
+    assert_eq!(test_replay(&player, head).as_deref(), Some(head_res));
+    assert_eq!(
+        test_replay(&player, "GET /y HTTP/1.1\r\nHost: a.test\r\n\r\n"),
+        None
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn cassette_config() {
+    let load = |text: &str| toml::from_str::<Cassette>(text).unwrap().load();
+    let missing = "mode = \"replay\"\npath = \"/nonexistent/cassette.http\"";
+    assert!(load(missing).is_err());
+    assert!(load("mode = \"replay\"\npath = \"x\"\nstatus = 200").is_err());
+    assert!(toml::from_str::<Cassette>("mode = \"play\"\npath = \"x\"").is_err());
+}
@@ -1,6 +1,19 @@
+use crate::auth::Auth;
+use crate::cache::Cache;
+use crate::cassette::Cassette;
+use crate::filter::{Blocklist, Rules};
+use crate::header::{default_rules, HeaderRule};
+use crate::pool::Pool;
+use crate::rule::UrlRule;
+use crate::user::{Group, Identity, IpUser, Selector};
+use regex::Regex;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::net::IpAddr;
+use std::str::FromStr;
 
 // Config field
 // #[derive(Deserialize)] is a Procedural Macros
@@ -11,15 +24,298 @@ pub struct Config {
     pub log: String,
     pub verbose: bool,
     pub thread: usize,
+    #[serde(default)]
+    pub keep_alive: KeepAlive,
+    // idle connections to servers
+    #[serde(default)]
+    pub pool: Pool,
     pub filter: Filter,
     pub redirect: Vec<Redirect>,
+    #[serde(default)]
+    pub rewrite: Vec<Rewrite>,
+    // policies of request and response headers, after default_rules()
+    #[serde(default)]
+    pub header: Vec<HeaderRule>,
+    #[serde(default)]
+    pub forward: Forward,
+    // proxy authentication, everyone can use proxy without it
+    pub auth: Option<Auth>,
+    // users known by address, and groups of users that rules are for
+    #[serde(default)]
+    pub user: Vec<IpUser>,
+    #[serde(default)]
+    pub group: Vec<Group>,
+    // cache of responses, every request goes to server without it
+    pub cache: Option<Cache>,
+    // record exchanges with servers, or replay them without asking servers
+    pub cassette: Option<Cassette>,
+    #[serde(default)]
+    pub error_page: ErrorPage,
+}
+
+// sub item
+#[derive(Deserialize)]
+pub struct KeepAlive {
+    // seconds to wait for next request from client
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    // max number of requests on one client connection
+    #[serde(default = "default_max")]
+    pub max: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_max() -> u64 {
+    100
+}
+
+impl Default for KeepAlive {
+    fn default() -> KeepAlive {
+        KeepAlive {
+            timeout: default_timeout(),
+            max: default_max(),
+        }
+    }
+}
+
+// sub item
+// headers that tell server and client a message pass through proxy
+#[derive(Deserialize)]
+pub struct Forward {
+    // name of proxy in Via, "Via: 1.1 proxy"
+    #[serde(default = "default_via")]
+    pub via: String,
+    // add address of client in X-Forwarded-For and Forwarded
+    #[serde(default)]
+    pub forwarded: bool,
+    // hide client and proxy from server, no Via, X-Forwarded-For or Forwarded
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+fn default_via() -> String {
+    "proxy".to_owned()
+}
+
+impl Default for Forward {
+    fn default() -> Forward {
+        Forward {
+            via: default_via(),
+            forwarded: false,
+            anonymous: false,
+        }
+    }
 }
 
 // sub item
 #[derive(Deserialize)]
 pub struct Filter {
+    // blacklist for website, see DomainMatcher for the syntax
+    // it is for everyone, rule below can block host for some users
     pub website: Vec<String>,
-    pub ip: Vec<String>,
+    // blacklist for user, checked before acl
+    #[serde(default)]
+    pub ip: Vec<Cidr>,
+    #[serde(default)]
+    pub acl: Acl,
+    // rules of url, checked before blocklist
+    #[serde(default)]
+    pub rule: Vec<UrlRule>,
+    // blocklist files, read when config is opened
+    #[serde(default)]
+    pub list: Vec<List>,
+    // website and lists for everyone above, built when config is opened
+    #[serde(skip)]
+    pub blocked: Blocklist,
+}
+
+#[derive(Deserialize)]
+pub struct List {
+    pub path: String,
+    pub format: ListFormat,
+    // users and groups it is for, everyone by default
+    #[serde(flatten)]
+    pub who: Selector,
+    // list of some users has its own blocklist
+    #[serde(skip)]
+    blocked: Blocklist,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    // "0.0.0.0 ads.example.com" in /etc/hosts
+    Hosts,
+    // Adblock Plus syntax, like EasyList
+    Adblock,
+}
+
+impl Filter {
+    // whether client of this address can use the proxy
+    pub fn allow_client(&self, ip: IpAddr) -> bool {
+        if self.ip.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.acl.check(ip) == Action::Allow
+    }
+
+    // website and lists, lists of other users are skipped
+    pub fn is_blocked(&self, host: &str, url: &str, who: &Identity) -> bool {
+        let of_user = |list: &&List| !list.who.is_everyone() && list.who.is_match(who);
+        self.blocked.is_blocked(host, url)
+            || self
+                .list
+                .iter()
+                .filter(of_user)
+                .any(|list| list.blocked.is_blocked(host, url))
+    }
+
+    fn load(&mut self) -> io::Result<()> {
+        for (i, rule) in self.rule.iter_mut().enumerate() {
+            rule.load().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("filter.rule[{}]: {}", i, e),
+                )
+            })?;
+        }
+        let mut rules = Rules {
+            block: self.website.clone(),
+            ..Rules::default()
+        };
+        for list in &mut self.list {
+            let mut text = String::new();
+            File::open(&list.path)
+                .and_then(|mut file| file.read_to_string(&mut text))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", list.path, e)))?;
+            if list.who.is_everyone() {
+                list.format.read(&mut rules, &text);
+                continue;
+            }
+            let mut own = Rules::default();
+            list.format.read(&mut own, &text);
+            list.blocked =
+                Blocklist::new(&own).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        self.blocked =
+            Blocklist::new(&rules).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(())
+    }
+}
+
+impl ListFormat {
+    fn read(self, rules: &mut Rules, text: &str) {
+        match self {
+            ListFormat::Hosts => rules.hosts(text),
+            ListFormat::Adblock => rules.adblock(text),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+// access control list of client address
+// rules are checked in order, the first matched rule decides,
+// client that match no rule get the default action
+// it is checked before client logs in, so it can't be for some users
+#[derive(Deserialize)]
+pub struct Acl {
+    pub default: Action,
+    #[serde(default)]
+    pub rule: Vec<AclRule>,
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl {
+            default: Action::Allow,
+            rule: Vec::new(),
+        }
+    }
+}
+
+impl Acl {
+    pub fn check(&self, ip: IpAddr) -> Action {
+        self.rule
+            .iter()
+            .find(|rule| rule.cidr.contains(ip))
+            .map_or(self.default, |rule| rule.action)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AclRule {
+    pub action: Action,
+    pub cidr: Cidr,
+}
+
+// address block like "10.0.0.0/8" or "2001:db8::/32"
+// an address without prefix length is a block of one address
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 client of a IPv6 socket looks like "::ffff:10.0.0.1"
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        // compare the first `prefix` bits, shift by full width means prefix 0
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) ^ u32::from(ip))
+                .checked_shr(32 - self.prefix)
+                .is_none_or(|diff| diff == 0),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net) ^ u128::from(ip))
+                .checked_shr(128 - self.prefix)
+                .is_none_or(|diff| diff == 0),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let (addr, prefix) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid address in {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+    fn try_from(s: String) -> Result<Cidr, String> {
+        s.parse()
+    }
 }
 
 // sub item
@@ -27,6 +323,118 @@ pub struct Filter {
 pub struct Redirect {
     pub from: String,
     pub to: String,
+    #[serde(default)]
+    pub mode: RedirectMode,
+    // status code of redirect mode, 302 by default
+    pub status: Option<u16>,
+    // users and groups it is for, everyone by default
+    #[serde(flatten)]
+    pub who: Selector,
+}
+
+// rewrite of the whole url with regex
+// pattern="^http://old\\.test/api/v1/(.*)$"
+// replace="http://new.test:8080/v2/$1"
+#[derive(Deserialize)]
+pub struct Rewrite {
+    pub pattern: String,
+    pub replace: String,
+    #[serde(flatten)]
+    pub who: Selector,
+    // pattern above, built when config is opened
+    #[serde(skip)]
+    pub regex: Option<Regex>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectMode {
+    // send request to `to` silently, browser still think it is on `from`
+    #[default]
+    Rewrite,
+    // tell browser to go to `to` with a 3xx response
+    Redirect,
+}
+
+impl Redirect {
+    pub fn status(&self) -> u16 {
+        self.status.unwrap_or(302)
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.status {
+            None | Some(301) | Some(302) | Some(307) | Some(308) => Ok(()),
+            Some(status) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("redirect from {} with status {}", self.from, status),
+            )),
+        }
+    }
+}
+
+// sub item
+#[derive(Deserialize)]
+pub struct ErrorPage {
+    // HTML of error page, "{code}" "{reason}" and "{message}" are replaced
+    #[serde(default = "default_template")]
+    pub template: String,
+    // template of a status code in a file, "502" = "502.html"
+    #[serde(default)]
+    pub file: HashMap<String, String>,
+    // content of files above, read when config is opened
+    #[serde(skip)]
+    pages: HashMap<u16, String>,
+}
+
+fn default_template() -> String {
+    "<html><head><title>{code} {reason}</title></head>\n\
+     <body><h1>{code} {reason}</h1><p>{message}</p></body></html>"
+        .to_owned()
+}
+
+impl Default for ErrorPage {
+    fn default() -> ErrorPage {
+        ErrorPage {
+            template: default_template(),
+            file: HashMap::new(),
+            pages: HashMap::new(),
+        }
+    }
+}
+
+impl ErrorPage {
+    // fill the template of this status code
+    pub fn render(&self, code: u16, reason: &str, message: &str) -> String {
+        let template = self.pages.get(&code).unwrap_or(&self.template);
+        template
+            .replace("{code}", &code.to_string())
+            .replace("{reason}", reason)
+            .replace("{message}", &escape_html(message))
+    }
+
+    fn load(&mut self) -> io::Result<()> {
+        for (code, path) in &self.file {
+            let code = code.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("error page of invalid status code {}", code),
+                )
+            })?;
+            let mut page = String::new();
+            File::open(path)?.read_to_string(&mut page)?;
+            self.pages.insert(code, page);
+        }
+        Ok(())
+    }
+}
+
+// message may contain host or path from client, don't let it become HTML
+fn escape_html(message: &str) -> String {
+    message
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 impl Config {
@@ -34,6 +442,228 @@ impl Config {
         let mut config_file = File::open("config.toml")?;
         let mut config_str = String::new();
         config_file.read_to_string(&mut config_str)?;
-        toml::from_str(&config_str).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        let mut config: Config = toml::from_str(&config_str)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        config.filter.load()?;
+        for redirect in &config.redirect {
+            redirect.check()?;
+        }
+        if let Some(auth) = &mut config.auth {
+            auth.load()?;
+        }
+        if let Some(cache) = &mut config.cache {
+            cache.load()?;
+        }
+        if let Some(cassette) = &mut config.cassette {
+            cassette.load()?;
+            // every request must reach cassette, cache would hide some of them
+            config.cache = None;
+        }
+        for (i, rule) in config.header.iter_mut().enumerate() {
+            rule.load().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("header[{}]: {}", i, e))
+            })?;
+        }
+        let mut rules = default_rules();
+        for rule in &mut rules {
+            rule.load()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        rules.append(&mut config.header);
+        config.header = rules;
+        for rewrite in &mut config.rewrite {
+            let regex = Regex::new(&rewrite.pattern)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            rewrite.regex = Some(regex);
+        }
+        config.error_page.load()?;
+        config.check_groups()?;
+        Ok(config)
+    }
+
+    // groups in rules must be defined
+    fn check_groups(&self) -> io::Result<()> {
+        let selectors = self
+            .filter
+            .rule
+            .iter()
+            .map(|rule| ("filter.rule", &rule.who))
+            .chain(
+                self.filter
+                    .list
+                    .iter()
+                    .map(|list| ("filter.list", &list.who)),
+            )
+            .chain(
+                self.redirect
+                    .iter()
+                    .map(|redirect| ("redirect", &redirect.who)),
+            )
+            .chain(self.rewrite.iter().map(|rewrite| ("rewrite", &rewrite.who)));
+        for (name, who) in selectors {
+            who.check(&self.group).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, e))
+            })?;
+        }
+        Ok(())
     }
 }
+
+// ************TEST*************//
+
+// rules of a piece of config.toml, like "[[header]]", checked as Config::open() does
+#[cfg(test)]
+pub fn test_rules<T, F>(toml: &str, load: F) -> Result<Vec<T>, String>
+where
+    T: serde::de::DeserializeOwned,
+    F: Fn(&mut T) -> Result<(), String>,
+{
+    let tables: HashMap<String, Vec<T>> = toml::from_str(toml).map_err(|e| e.to_string())?;
+    let mut rules: Vec<T> = tables.into_values().flatten().collect();
+    for rule in &mut rules {
+        load(rule)?;
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn cidr_parse() {
+    assert!("10.0.0.0/8".parse::<Cidr>().is_ok());
+    assert!("1.2.3.4".parse::<Cidr>().is_ok());
+    assert!("2001:db8::/32".parse::<Cidr>().is_ok());
+    assert!("::/0".parse::<Cidr>().is_ok());
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("10.0.0/8".parse::<Cidr>().is_err());
+    assert!("2001:db8::/129".parse::<Cidr>().is_err());
+    assert!("10.0.0.0/".parse::<Cidr>().is_err());
+    assert!("example.com".parse::<Cidr>().is_err());
+}
+
+#[test]
+fn cidr_contains() {
+    let net: Cidr = "10.0.0.0/8".parse().unwrap();
+    assert!(net.contains(ip("10.1.2.3")));
+    assert!(net.contains(ip("::ffff:10.1.2.3")));
+    assert!(!net.contains(ip("11.0.0.0")));
+    assert!(!net.contains(ip("::1")));
+    let one: Cidr = "192.168.1.148".parse().unwrap();
+    assert!(one.contains(ip("192.168.1.148")));
+    assert!(!one.contains(ip("192.168.1.149")));
+    let all: Cidr = "0.0.0.0/0".parse().unwrap();
+    assert!(all.contains(ip("255.255.255.255")));
+    let v6: Cidr = "2001:db8::/32".parse().unwrap();
+    assert!(v6.contains(ip("2001:db8:1::1")));
+    assert!(!v6.contains(ip("2001:db9::1")));
+    assert!(!v6.contains(ip("10.0.0.1")));
+}
+
+#[test]
+fn acl_order() {
+    let acl: Acl = toml::from_str(
+        r#"
+        default = "deny"
+        rule = [
+            { action = "deny", cidr = "192.168.1.100" },
+            { action = "allow", cidr = "192.168.0.0/16" },
+            { action = "allow", cidr = "fd00::/8" },
+        ]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(acl.check(ip("192.168.1.100")), Action::Deny);
+    assert_eq!(acl.check(ip("192.168.1.101")), Action::Allow);
+    assert_eq!(acl.check(ip("fd12::1")), Action::Allow);
+    assert_eq!(acl.check(ip("8.8.8.8")), Action::Deny);
+    assert_eq!(Acl::default().check(ip("8.8.8.8")), Action::Allow);
+    let bad = r#"
+        default = "deny"
+        rule = [{ action = "allow", cidr = "1.2.3.4/40" }]
+        "#;
+    assert!(toml::from_str::<Acl>(bad).is_err());
+}
+
+#[test]
+fn redirect_mode() {
+    #[derive(Deserialize)]
+    struct Redirects {
+        redirect: Vec<Redirect>,
+    }
+    let r: Redirects = toml::from_str(
+        r#"
+        [[redirect]]
+        from = "a.test"
+        to = "b.test"
+        [[redirect]]
+        from = "c.test"
+        to = "d.test:8080"
+        mode = "redirect"
+        status = 308
+        [[redirect]]
+        from = "e.test"
+        to = "f.test"
+        mode = "redirect"
+        status = 200
+        "#,
+    )
+    .unwrap();
+    assert_eq!(r.redirect[0].mode, RedirectMode::Rewrite);
+    assert_eq!(r.redirect[0].status(), 302);
+    assert!(r.redirect[0].check().is_ok());
+    assert_eq!(r.redirect[1].mode, RedirectMode::Redirect);
+    assert_eq!(r.redirect[1].status(), 308);
+    assert!(r.redirect[1].check().is_ok());
+    assert!(r.redirect[2].check().is_err());
+}
+
+#[test]
+fn list_of_users() {
+    let dir = std::env::temp_dir().join(format!("proxy-list-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (all, interns) = (dir.join("all.txt"), dir.join("interns.txt"));
+    std::fs::write(&all, "0.0.0.0 ads.test\n").unwrap();
+    std::fs::write(&interns, "0.0.0.0 games.test\n").unwrap();
+    let mut filter: Filter = toml::from_str(&format!(
+        r#"
+        website = ["bad.test"]
+        [[list]]
+        path = {:?}
+        format = "hosts"
+        [[list]]
+        path = {:?}
+        format = "hosts"
+        groups = ["interns"]
+        "#,
+        all, interns
+    ))
+    .unwrap();
+    filter.load().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    let intern = Identity {
+        name: Some("bob".to_owned()),
+        groups: vec!["interns".to_owned()],
+    };
+    let admin = Identity {
+        name: Some("alice".to_owned()),
+        groups: Vec::new(),
+    };
+    for who in &[&intern, &admin] {
+        assert!(filter.is_blocked("bad.test", "http://bad.test/", who));
+        assert!(filter.is_blocked("ads.test", "http://ads.test/", who));
+    }
+    assert!(filter.is_blocked("games.test", "http://games.test/", &intern));
+    assert!(!filter.is_blocked("games.test", "http://games.test/", &admin));
+    assert_eq!(filter.blocked.len(), 2);
+}
+
+#[test]
+fn keep_alive_default() {
+    let keep_alive: KeepAlive = toml::from_str("").unwrap();
+    assert_eq!((keep_alive.timeout, keep_alive.max), (5, 100));
+    let keep_alive: KeepAlive = toml::from_str("max = 10").unwrap();
+    assert_eq!((keep_alive.timeout, keep_alive.max), (5, 10));
+}
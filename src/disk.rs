@@ -0,0 +1,491 @@
+use crate::cache::{vary_match, Entry};
+use crate::http::Headers;
+use md5::{Digest, Md5};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// journal of cache directory, a line for every change
+// "CLEAN name size" after a file is written, "READ name" after it is used,
+// "REMOVE name" before it is deleted
+// it is replayed when directory is opened, files not in it are half written
+const JOURNAL: &str = "index";
+// journal is written again without old lines when it has this many lines
+const JOURNAL_MAX: usize = 2000;
+// gives temporary files different names, threads may write the same entry together
+static TMP_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// responses in a directory, a file for each one
+// file is written to "name.N.tmp" then renamed, so it is never seen half written
+pub struct Disk {
+    dir: PathBuf,
+    // bytes of files, least recently used ones are deleted
+    budget: u64,
+    files: HashMap<String, DiskFile>,
+    // key of request -> names of files with different Vary values
+    keys: HashMap<String, Vec<String>>,
+    // last use -> name of file
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    size: u64,
+    journal: File,
+    lines: usize,
+    // files found and broken ones deleted when directory is opened
+    pub recovered: usize,
+    pub discarded: usize,
+}
+
+// request headers named in Vary and their values, see Entry
+type Vary = Vec<(String, Option<Vec<u8>>)>;
+
+// entry in a temporary file, it is not in directory before Disk::commit()
+pub struct Written {
+    tmp: PathBuf,
+    name: String,
+    size: u64,
+}
+
+struct DiskFile {
+    key: String,
+    vary: Vary,
+    size: u64,
+    used: u64,
+}
+
+impl Disk {
+    // replay journal, and delete files that are broken or not in it
+    pub fn open(dir: &str, budget: u64) -> io::Result<Disk> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        // name -> size and number of line it is used last time
+        let mut clean: HashMap<String, (u64, usize)> = HashMap::new();
+        if let Ok(file) = File::open(dir.join(JOURNAL)) {
+            // last line may be half written, then it is not valid
+            for (i, line) in BufReader::new(file).split(b'\n').enumerate() {
+                let line = String::from_utf8_lossy(&line?).into_owned();
+                let words: Vec<&str> = line.split(' ').collect();
+                match words[..] {
+                    ["CLEAN", name, size] => {
+                        if let Ok(size) = size.parse() {
+                            clean.insert(name.to_owned(), (size, i));
+                        }
+                    }
+                    ["READ", name] => {
+                        if let Some(used) = clean.get_mut(name) {
+                            used.1 = i;
+                        }
+                    }
+                    ["REMOVE", name] => {
+                        clean.remove(name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut clean: Vec<(String, u64, usize)> = clean
+            .into_iter()
+            .map(|(name, (size, i))| (name, size, i))
+            .collect();
+        clean.sort_by_key(|&(_, _, i)| i);
+        let journal = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dir.join(JOURNAL))?;
+        let mut disk = Disk {
+            journal,
+            dir,
+            budget,
+            files: HashMap::new(),
+            keys: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            lines: 0,
+            recovered: 0,
+            discarded: 0,
+        };
+        for (name, size, _) in clean {
+            match disk.check(&name, size) {
+                Some(entry) => {
+                    disk.insert(name, entry.key, entry.vary, size);
+                    disk.recovered += 1;
+                }
+                None => {
+                    let _ = fs::remove_file(disk.dir.join(&name));
+                    disk.discarded += 1;
+                }
+            }
+        }
+        // temporary files and files written before their CLEAN line
+        // directory may be shared, other files are not ours
+        for file in fs::read_dir(&disk.dir)? {
+            let file = file?;
+            let name = file.file_name().to_string_lossy().into_owned();
+            let ours = is_name(&name) || is_tmp(&name);
+            if ours && !disk.files.contains_key(&name) && file.file_type()?.is_file() {
+                fs::remove_file(file.path())?;
+                disk.discarded += 1;
+            }
+        }
+        disk.compact()?;
+        disk.evict()?;
+        Ok(disk)
+    }
+
+    // entry in file if file is complete
+    fn check(&self, name: &str, size: u64) -> Option<Entry> {
+        let file = File::open(self.dir.join(name)).ok()?;
+        if !is_name(name) || file.metadata().ok()?.len() != size {
+            return None;
+        }
+        Entry::decode(&mut BufReader::new(file), size, false).ok()
+    }
+
+    // file name of stored response of request with the same Vary values
+    pub fn find(&self, key: &str, headers: &Headers) -> Option<String> {
+        self.keys
+            .get(key)?
+            .iter()
+            .find(|name| vary_match(&self.files[*name].vary, headers))
+            .cloned()
+    }
+
+    // read a file found by find(), then give the result to loaded()
+    // it doesn't need a Disk, so other threads don't wait for the read
+    pub fn read(dir: &str, name: &str) -> io::Result<Entry> {
+        let file = File::open(PathBuf::from(dir).join(name))?;
+        let len = file.metadata()?.len();
+        Entry::decode(&mut BufReader::new(file), len, true)
+    }
+
+    pub fn loaded(&mut self, name: &str, read: io::Result<Entry>) -> Option<Entry> {
+        match read {
+            Ok(entry) => {
+                self.touch(name);
+                if let Err(e) = self.log(&format!("READ {}", name)) {
+                    warn!("failed to write journal of cache, {}", e);
+                }
+                Some(entry)
+            }
+            // another thread has removed or evicted it
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("cache file {} is broken, {}", name, e);
+                let _ = self.remove(name);
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn load(&mut self, key: &str, headers: &Headers) -> Option<Entry> {
+        let name = self.find(key, headers)?;
+        let read = Disk::read(&self.dir.to_string_lossy(), &name);
+        self.loaded(&name, read)
+    }
+
+    // write entry to a temporary file in `dir`, then commit() puts it in directory
+    // it doesn't need a Disk, so the slow sync is done without holding lock of cache
+    pub fn write(dir: &str, entry: &Entry) -> io::Result<Written> {
+        let data = entry.encode();
+        let name = file_name(entry);
+        let count = TMP_COUNT.fetch_add(1, Ordering::Relaxed);
+        let tmp = PathBuf::from(dir).join(format!("{}.{}.tmp", name, count));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        // data must be on disk before rename, or a crash may leave an empty file
+        file.sync_all()?;
+        Ok(Written {
+            tmp,
+            name,
+            size: data.len() as u64,
+        })
+    }
+
+    // rename written file of entry, the old one with the same key and Vary is replaced
+    pub fn commit(&mut self, entry: &Entry, written: Written) -> io::Result<()> {
+        let Written { tmp, name, size } = written;
+        if size > self.budget {
+            return fs::remove_file(&tmp);
+        }
+        fs::rename(&tmp, self.dir.join(&name))?;
+        self.forget(&name);
+        self.insert(name.clone(), entry.key.clone(), entry.vary.clone(), size);
+        self.log(&format!("CLEAN {} {}", name, size))?;
+        self.evict()
+    }
+
+    #[cfg(test)]
+    fn save(&mut self, entry: &Entry) -> io::Result<()> {
+        let written = Disk::write(&self.dir.to_string_lossy(), entry)?;
+        self.commit(entry, written)
+    }
+
+    // delete all responses of a request
+    pub fn remove_key(&mut self, key: &str) {
+        let names = self.keys.get(key).cloned().unwrap_or_default();
+        for name in names {
+            if let Err(e) = self.remove(&name) {
+                warn!("failed to remove cache file {}, {}", name, e);
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &str) -> io::Result<()> {
+        self.log(&format!("REMOVE {}", name))?;
+        self.forget(name);
+        match fs::remove_file(self.dir.join(name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert(&mut self, name: String, key: String, vary: Vary, size: u64) {
+        self.tick += 1;
+        self.lru.insert(self.tick, name.clone());
+        self.keys.entry(key.clone()).or_default().push(name.clone());
+        self.size += size;
+        let file = DiskFile {
+            key,
+            vary,
+            size,
+            used: self.tick,
+        };
+        self.files.insert(name, file);
+    }
+
+    // remove file from index, file itself is kept
+    fn forget(&mut self, name: &str) {
+        let file = match self.files.remove(name) {
+            Some(file) => file,
+            None => return,
+        };
+        self.lru.remove(&file.used);
+        self.size -= file.size;
+        if let Some(names) = self.keys.get_mut(&file.key) {
+            names.retain(|x| x != name);
+            if names.is_empty() {
+                self.keys.remove(&file.key);
+            }
+        }
+    }
+
+    fn touch(&mut self, name: &str) {
+        self.tick += 1;
+        if let Some(file) = self.files.get_mut(name) {
+            self.lru.remove(&file.used);
+            file.used = self.tick;
+            self.lru.insert(self.tick, name.to_owned());
+        }
+    }
+
+    fn evict(&mut self) -> io::Result<()> {
+        while self.size > self.budget {
+            let name = match self.lru.values().next() {
+                Some(name) => name.clone(),
+                None => break,
+            };
+            self.remove(&name)?;
+        }
+        Ok(())
+    }
+
+    fn log(&mut self, line: &str) -> io::Result<()> {
+        self.journal.write_all(format!("{}\n", line).as_bytes())?;
+        self.lines += 1;
+        if self.lines > JOURNAL_MAX && self.lines > 2 * self.files.len() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    // write journal again with a CLEAN line for each file, in order of use
+    fn compact(&mut self) -> io::Result<()> {
+        let mut text = String::new();
+        for name in self.lru.values() {
+            text.push_str(&format!("CLEAN {} {}\n", name, self.files[name].size));
+        }
+        let tmp = self.dir.join(format!("{}.tmp", JOURNAL));
+        let mut file = File::create(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(JOURNAL))?;
+        self.journal = OpenOptions::new()
+            .append(true)
+            .open(self.dir.join(JOURNAL))?;
+        self.lines = self.files.len();
+        Ok(())
+    }
+}
+
+// md5 of key and Vary values, so the same response always has the same file
+fn file_name(entry: &Entry) -> String {
+    let mut md5 = Md5::new();
+    md5.update(entry.key.as_bytes());
+    for (name, value) in &entry.vary {
+        md5.update(b"\n");
+        md5.update(name.as_bytes());
+        if let Some(value) = value {
+            md5.update(b": ");
+            md5.update(value);
+        }
+    }
+    md5.finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn is_name(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+// "name.N.tmp" of an entry or "index.tmp" of journal
+fn is_tmp(name: &str) -> bool {
+    match name.strip_suffix(".tmp") {
+        Some(JOURNAL) => true,
+        Some(stem) => stem.split('.').next().is_some_and(is_name),
+        None => false,
+    }
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn test_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("proxy-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+fn test_entry(path: &str, body: &str) -> Entry {
+    use crate::cache::Cache;
+    use crate::http::{Request, Response};
+    let cache: Cache = toml::from_str("memory = 0").unwrap();
+    let req = format!(
+        "GET {} HTTP/1.1\r\nHost: a.test\r\nAccept: text/html\r\n\r\n",
+        path
+    );
+    let req = Request::parse(req.as_bytes()).unwrap();
+    let res = b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept, Cookie\r\n\r\n";
+    let res = Response::parse(res).unwrap();
+    let mut entry = cache.entry(&req, &res, 1000, 1001).unwrap();
+    entry.body = body.as_bytes().to_vec();
+    entry
+}
+
+#[cfg(test)]
+fn test_load(disk: &mut Disk, path: &str) -> Option<String> {
+    let mut headers = Headers::new();
+    headers.insert("Accept", "text/html");
+    let entry = disk.load(&format!("GET http://a.test{}", path), &headers)?;
+    Some(String::from_utf8(entry.body).unwrap())
+}
+
+#[test]
+fn entry_encode() {
+    let entry = test_entry("/x", "hello");
+    let data = entry.encode();
+    let len = data.len() as u64;
+    let decoded = Entry::decode(&mut &data[..], len, true).unwrap();
+    assert_eq!(decoded.encode(), data);
+    assert_eq!(decoded.vary, entry.vary);
+    assert_eq!(decoded.age(1011), 11);
+    let decoded = Entry::decode(&mut &data[..], len, false).unwrap();
+    assert!(decoded.body.is_empty() && decoded.head == entry.head);
+    assert!(Entry::decode(&mut &data[..data.len() - 1], len - 1, true).is_err());
+    assert!(Entry::decode(&mut &b"PROXY CACHE 0\r\n"[..], 15, true).is_err());
+    // length in file is not trusted
+    let broken = String::from_utf8(data.clone())
+        .unwrap()
+        .replace(" 5\r\n", " 99999999999\r\n");
+    assert!(Entry::decode(&mut broken.as_bytes(), broken.len() as u64, true).is_err());
+}
+
+#[test]
+fn disk_survive_restart() {
+    let dir = test_dir("restart");
+    let mut disk = Disk::open(&dir, 1 << 20).unwrap();
+    disk.save(&test_entry("/a", "first")).unwrap();
+    disk.save(&test_entry("/b", "second")).unwrap();
+    disk.save(&test_entry("/a", "third")).unwrap();
+    disk.remove_key("GET http://a.test/b");
+    assert_eq!(test_load(&mut disk, "/a").as_deref(), Some("third"));
+    drop(disk);
+    let mut disk = Disk::open(&dir, 1 << 20).unwrap();
+    assert_eq!((disk.recovered, disk.discarded), (1, 0));
+    assert_eq!(test_load(&mut disk, "/a").as_deref(), Some("third"));
+    assert_eq!(test_load(&mut disk, "/b"), None);
+    // another value of Vary header
+    let mut headers = Headers::new();
+    headers.insert("Accept", "image/png");
+    assert!(disk.load("GET http://a.test/a", &headers).is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disk_recover_broken_files() {
+    let dir = test_dir("recover");
+    let mut disk = Disk::open(&dir, 1 << 20).unwrap();
+    let entries = [
+        test_entry("/a", "a"),
+        test_entry("/b", "b"),
+        test_entry("/c", "c"),
+    ];
+    for entry in &entries {
+        disk.save(entry).unwrap();
+    }
+    drop(disk);
+    // crash when writing a file, after renaming a file, and half written journal
+    let path = |entry: &Entry| PathBuf::from(&dir).join(file_name(entry));
+    let tmp = "0123456789abcdef0123456789abcdef.7.tmp";
+    fs::write(PathBuf::from(&dir).join(tmp), b"PROXY").unwrap();
+    // file of someone else in the same directory
+    fs::write(PathBuf::from(&dir).join("notes.txt"), b"mine").unwrap();
+    let data = fs::read(path(&entries[1])).unwrap();
+    fs::write(path(&entries[1]), &data[..data.len() - 1]).unwrap();
+    fs::write(
+        PathBuf::from(&dir).join("0123456789abcdef0123456789abcdef"),
+        b"",
+    )
+    .unwrap();
+    let mut journal = OpenOptions::new()
+        .append(true)
+        .open(PathBuf::from(&dir).join(JOURNAL));
+    journal.as_mut().unwrap().write_all(b"CLEAN 99").unwrap();
+    let mut disk = Disk::open(&dir, 1 << 20).unwrap();
+    assert_eq!((disk.recovered, disk.discarded), (2, 3));
+    assert_eq!(test_load(&mut disk, "/a").as_deref(), Some("a"));
+    assert_eq!(test_load(&mut disk, "/b"), None);
+    assert_eq!(test_load(&mut disk, "/c").as_deref(), Some("c"));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+    assert!(PathBuf::from(&dir).join("notes.txt").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disk_evict() {
+    let dir = test_dir("evict");
+    let body = "x".repeat(100);
+    let size = test_entry("/a", &body).encode().len() as u64;
+    let mut disk = Disk::open(&dir, 3 * size).unwrap();
+    disk.save(&test_entry("/a", &body)).unwrap();
+    disk.save(&test_entry("/b", &body)).unwrap();
+    disk.save(&test_entry("/c", &body)).unwrap();
+    assert!(test_load(&mut disk, "/a").is_some());
+    disk.save(&test_entry("/d", &body)).unwrap();
+    assert!(test_load(&mut disk, "/b").is_none());
+    drop(disk);
+    // order of use is kept in journal
+    let mut disk = Disk::open(&dir, 2 * size).unwrap();
+    assert_eq!(disk.recovered, 3);
+    assert!(test_load(&mut disk, "/c").is_none());
+    assert!(test_load(&mut disk, "/a").is_some());
+    assert!(test_load(&mut disk, "/d").is_some());
+    fs::remove_dir_all(&dir).unwrap();
+}
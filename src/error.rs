@@ -0,0 +1,75 @@
+use crate::http::ParseError;
+use std::fmt;
+use std::io;
+
+// Everything that can go wrong when handling a client
+// Most of them can be told to client with a HTTP status code,
+// so that browser show an error page instead of an empty reply.
+#[derive(Debug)]
+pub enum Error {
+    // client send a malformed request
+    BadRequest(ParseError),
+    // can't read from or write to client
+    Client(io::Error),
+    // can't resolve host name of server
+    Resolve(String, io::Error),
+    // can't connect to server
+    Connect(String, io::Error),
+    // server don't answer in time
+    Timeout(String),
+    // server send a malformed response or close connection without response
+    BadResponse(String, String),
+    // connection broken after response has been sent to client
+    Forward(io::Error),
+}
+
+impl Error {
+    // status code to send to client, None if it is too late to send one
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::BadRequest(_) => Some(400),
+            Error::Resolve(..) => Some(503),
+            Error::Connect(_, e) if is_timeout(e) => Some(504),
+            Error::Connect(..) => Some(502),
+            Error::Timeout(_) => Some(504),
+            Error::BadResponse(..) => Some(502),
+            Error::Client(_) | Error::Forward(_) => None,
+        }
+    }
+
+    // io error when talking to server before response is sent to client
+    pub fn server(host: &str, e: io::Error) -> Error {
+        if is_timeout(&e) {
+            Error::Timeout(host.to_owned())
+        } else {
+            Error::BadResponse(host.to_owned(), e.to_string())
+        }
+    }
+}
+
+// read timeout is WouldBlock on unix and TimedOut on windows
+pub fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadRequest(e) => write!(f, "bad request from client, {}", e),
+            Error::Client(e) => write!(f, "can't talk to client, {}", e),
+            Error::Resolve(host, e) => write!(f, "unable to resolve host {}, {}", host, e),
+            Error::Connect(host, e) => write!(f, "Error to connect to Server {} : {}", host, e),
+            Error::Timeout(host) => write!(f, "Server {} don't response in time", host),
+            Error::BadResponse(host, e) => write!(f, "bad response from server {}, {}", host, e),
+            Error::Forward(e) => write!(f, "can't forward message, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::BadRequest(e)
+    }
+}
@@ -0,0 +1,443 @@
+use regex::{Regex, RegexSet, RegexSetBuilder};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// compiled regex of a big list can be larger than the default limit of regex crate
+const REGEX_SIZE_LIMIT: usize = 1 << 28;
+
+// rules collected from config and blocklist files
+// domain rules use the syntax of DomainMatcher, url rules are regular expressions
+#[derive(Default)]
+pub struct Rules {
+    pub block: Vec<String>,
+    pub allow: Vec<String>,
+    pub block_url: Vec<String>,
+    pub allow_url: Vec<String>,
+    // rules we don't understand, like element hiding and options of Adblock
+    pub skipped: usize,
+}
+
+impl Rules {
+    // hosts file, "0.0.0.0 ads.example.com tracker.example.com # comment"
+    // every name in it is blocked, names of this machine are ignored
+    pub fn hosts(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut names = line.split_whitespace().peekable();
+            // some lists only have names without address
+            if let Some(addr) = names.peek() {
+                if addr.parse::<IpAddr>().is_ok() {
+                    names.next();
+                }
+            }
+            for name in names {
+                match name {
+                    "localhost"
+                    | "localhost.localdomain"
+                    | "local"
+                    | "broadcasthost"
+                    | "ip6-localhost"
+                    | "ip6-loopback" => {}
+                    name => self.block.push(name.to_owned()),
+                }
+            }
+        }
+    }
+
+    // Adblock Plus filter list, like EasyList
+    // "||example.com^"       example.com and its subdomains
+    // "/banner/*/img^"       url contains it, "*" is anything, "^" is a separator
+    // "|http://example.com|" url starts or ends with it
+    // "/ads?[0-9]/"          regular expression
+    // "@@||good.example.com^" exception, never block it
+    // "! comment"
+    pub fn adblock(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim();
+            // comment and header "[Adblock Plus 2.0]"
+            if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+                continue;
+            }
+            // element hiding only works in browser
+            if ["##", "#@#", "#?#", "#$#"].iter().any(|x| line.contains(x)) {
+                self.skipped += 1;
+                continue;
+            }
+            let (exception, rule) = match line.strip_prefix("@@") {
+                Some(rule) => (true, rule),
+                None => (false, line),
+            };
+            let is_regex = rule.len() > 1 && rule.starts_with('/') && rule.ends_with('/');
+            // options like "$third-party" need to know who load the url, we can't check them
+            // skip the rule instead of blocking too much
+            if rule.is_empty() || (!is_regex && rule.contains('$')) {
+                self.skipped += 1;
+                continue;
+            }
+            if let Some(domain) = adblock_domain(rule) {
+                let domain = format!(".{}", domain);
+                match exception {
+                    true => self.allow.push(domain),
+                    false => self.block.push(domain),
+                }
+                continue;
+            }
+            let re = adblock_regex(rule);
+            // a bad regex in a big list should not stop the proxy
+            if is_regex && Regex::new(&re).is_err() {
+                self.skipped += 1;
+                continue;
+            }
+            match exception {
+                true => self.allow_url.push(re),
+                false => self.block_url.push(re),
+            }
+        }
+    }
+}
+
+// "||example.com^" only match domain, it can go to DomainMatcher
+fn adblock_domain(rule: &str) -> Option<&str> {
+    let domain = rule.strip_prefix("||")?;
+    let domain = domain
+        .strip_suffix("^|")
+        .or_else(|| domain.strip_suffix('^'))?;
+    let valid = !domain.is_empty()
+        && domain
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'.' || c == b'-' || c == b'_');
+    if valid {
+        Some(domain)
+    } else {
+        None
+    }
+}
+
+// translate Adblock pattern to regular expression of url
+fn adblock_regex(rule: &str) -> String {
+    if rule.len() > 1 && rule.starts_with('/') && rule.ends_with('/') {
+        return rule[1..rule.len() - 1].to_owned();
+    }
+    let mut re = String::new();
+    let mut rest = rule;
+    if let Some(domain) = rest.strip_prefix("||") {
+        // scheme and any subdomain
+        re.push_str(r"^[a-z][a-z0-9+.\-]*://([^/?#]*\.)?");
+        rest = domain;
+    } else if let Some(start) = rest.strip_prefix('|') {
+        re.push('^');
+        rest = start;
+    }
+    let end = match rest.strip_suffix('|') {
+        Some(body) => {
+            rest = body;
+            true
+        }
+        None => false,
+    };
+    let mut buf = [0; 4];
+    for c in rest.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            // separator is anything but a letter, a digit, or one of "_-.%", or end of url
+            '^' => re.push_str(r"(?:[^\w\-.%]|$)"),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut buf))),
+        }
+    }
+    if end {
+        re.push('$');
+    }
+    re
+}
+
+// everything that decide whether a request is blocked
+// a request is blocked if its host or url match a block rule,
+// unless it also match an exception rule
+#[derive(Default)]
+pub struct Blocklist {
+    block: DomainMatcher,
+    allow: DomainMatcher,
+    block_url: Option<RegexSet>,
+    allow_url: Option<RegexSet>,
+    len: usize,
+    skipped: usize,
+}
+
+impl Blocklist {
+    pub fn new(rules: &Rules) -> Result<Blocklist, regex::Error> {
+        let block = DomainMatcher::new(&rules.block)?;
+        let allow = DomainMatcher::new(&rules.allow)?;
+        Ok(Blocklist {
+            len: block.len() + allow.len() + rules.block_url.len() + rules.allow_url.len(),
+            block,
+            allow,
+            block_url: url_set(&rules.block_url)?,
+            allow_url: url_set(&rules.allow_url)?,
+            skipped: rules.skipped,
+        })
+    }
+
+    // number of rules
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // number of rules that are ignored
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn is_blocked(&self, host: &str, url: &str) -> bool {
+        let url_match = |set: &Option<RegexSet>| set.as_ref().is_some_and(|set| set.is_match(url));
+        (self.block.is_match(host) || url_match(&self.block_url))
+            && !(self.allow.is_match(host) || url_match(&self.allow_url))
+    }
+}
+
+fn url_set(patterns: &[String]) -> Result<Option<RegexSet>, regex::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map(Some)
+}
+
+// Match host names against a list of domain rules
+// "example.com"    only example.com
+// "*.example.com"  subdomains of example.com, but not example.com itself
+// ".example.com"   example.com and all its subdomains
+// "/ad[0-9]+\./"   regular expression, between two slashes
+// Domain names are case-insensitive, so everything is lowercased.
+#[derive(Default)]
+pub struct DomainMatcher {
+    // labels are stored from right to left, "www.example.com" is com -> example -> www
+    // so that finding a suffix only walks as many nodes as the host has labels
+    root: Node,
+    regex: Option<RegexSet>,
+    len: usize,
+}
+
+#[derive(Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    // the domain of this node is in the list
+    exact: bool,
+    // every domain below this node is in the list
+    subdomain: bool,
+}
+
+impl DomainMatcher {
+    pub fn new<S: AsRef<str>>(rules: &[S]) -> Result<DomainMatcher, regex::Error> {
+        let mut matcher = DomainMatcher::default();
+        let mut patterns = Vec::new();
+        for rule in rules {
+            let rule = rule.as_ref().trim();
+            if rule.len() > 1 && rule.starts_with('/') && rule.ends_with('/') {
+                patterns.push(&rule[1..rule.len() - 1]);
+                matcher.len += 1;
+            } else {
+                matcher.insert(rule);
+            }
+        }
+        if !patterns.is_empty() {
+            // RegexSet test all patterns in a single pass
+            let set = RegexSetBuilder::new(patterns)
+                .case_insensitive(true)
+                .build()?;
+            matcher.regex = Some(set);
+        }
+        Ok(matcher)
+    }
+
+    // add a rule that is not a regex
+    pub fn insert(&mut self, rule: &str) {
+        let rule = rule.trim().trim_end_matches('.').to_ascii_lowercase();
+        let (name, exact, subdomain) = if let Some(name) = rule.strip_prefix("*.") {
+            (name, false, true)
+        } else if let Some(name) = rule.strip_prefix('.') {
+            (name, true, true)
+        } else if rule == "*" {
+            ("", false, true)
+        } else {
+            (&rule[..], true, false)
+        };
+        if name.is_empty() && !subdomain {
+            return;
+        }
+        let mut node = &mut self.root;
+        for label in labels(name) {
+            node = node.children.entry(label.to_owned()).or_default();
+        }
+        node.exact |= exact;
+        node.subdomain |= subdomain;
+        self.len += 1;
+    }
+
+    // number of rules
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_match(&self, host: &str) -> bool {
+        // "Example.COM." is the same domain as "example.com"
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() {
+            return false;
+        }
+        let mut node = &self.root;
+        for label in labels(&host) {
+            if node.subdomain {
+                return true;
+            }
+            node = match node.children.get(label) {
+                Some(child) => child,
+                None => return self.regex_match(&host),
+            };
+        }
+        node.exact || self.regex_match(&host)
+    }
+
+    fn regex_match(&self, host: &str) -> bool {
+        self.regex.as_ref().is_some_and(|set| set.is_match(host))
+    }
+}
+
+// labels of a domain from right to left, empty domain has no label
+fn labels(name: &str) -> impl Iterator<Item = &str> {
+    name.rsplit('.').filter(|label| !label.is_empty())
+}
+
+// ************TEST*************//
+
+#[test]
+fn domain_exact() {
+    let m = DomainMatcher::new(&["example.com"]).unwrap();
+    assert!(m.is_match("example.com"));
+    assert!(m.is_match("EXAMPLE.com"));
+    assert!(m.is_match("example.com."));
+    assert!(!m.is_match("www.example.com"));
+    assert!(!m.is_match("com"));
+    assert!(!m.is_match("badexample.com"));
+    assert!(!m.is_match(""));
+}
+
+#[test]
+fn domain_wildcard() {
+    let m = DomainMatcher::new(&["*.example.com"]).unwrap();
+    assert!(!m.is_match("example.com"));
+    assert!(m.is_match("www.example.com"));
+    assert!(m.is_match("a.b.Example.com"));
+    assert!(!m.is_match("badexample.com"));
+    assert!(!m.is_match("example.org"));
+}
+
+#[test]
+fn domain_suffix() {
+    let m = DomainMatcher::new(&[".Example.com"]).unwrap();
+    assert!(m.is_match("example.com"));
+    assert!(m.is_match("cdn.example.com"));
+    assert!(m.is_match("a.cdn.example.com"));
+    assert!(!m.is_match("badexample.com"));
+    assert!(!m.is_match("com"));
+}
+
+#[test]
+fn domain_regex() {
+    let m = DomainMatcher::new(&["/^ad[0-9]+\\./", "/tracker/"]).unwrap();
+    assert!(m.is_match("ad1.example.com"));
+    assert!(m.is_match("AD22.example.com"));
+    assert!(m.is_match("www.tracker.net"));
+    assert!(!m.is_match("www.ad1.example.com"));
+    assert!(DomainMatcher::new(&["/(/"]).is_err());
+}
+
+#[test]
+fn domain_mixed() {
+    let m = DomainMatcher::new(&["a.com", "*.b.com", ".c.com", "*", "/x/"]).unwrap();
+    assert_eq!(m.len(), 5);
+    assert!(m.is_match("anything.org"));
+    let m = DomainMatcher::new(&["a.com", "*.a.com"]).unwrap();
+    assert!(m.is_match("a.com"));
+    assert!(m.is_match("www.a.com"));
+    let m = DomainMatcher::new::<&str>(&[]).unwrap();
+    assert_eq!(m.len(), 0);
+    assert!(!m.is_match("a.com"));
+}
+
+#[test]
+fn domain_many() {
+    let rules: Vec<String> = (0..50000).map(|i| format!(".site{}.com", i)).collect();
+    let m = DomainMatcher::new(&rules).unwrap();
+    assert!(m.is_match("www.site49999.com"));
+    assert!(m.is_match("site0.com"));
+    assert!(!m.is_match("site50000.com"));
+}
+
+#[test]
+fn hosts_file() {
+    let mut rules = Rules::default();
+    rules.hosts(
+        "# comment\n127.0.0.1 localhost\n::1 ip6-localhost\n\
+         0.0.0.0 ads.example.com tracker.example.com # inline\n\
+         plain.example.com\n",
+    );
+    assert_eq!(
+        rules.block,
+        [
+            "ads.example.com",
+            "tracker.example.com",
+            "plain.example.com"
+        ]
+    );
+    let list = Blocklist::new(&rules).unwrap();
+    assert!(list.is_blocked("ADS.example.com", "http://ads.example.com/"));
+    assert!(!list.is_blocked("www.ads.example.com", "http://www.ads.example.com/"));
+    assert!(!list.is_blocked("localhost", "http://localhost/"));
+}
+
+#[test]
+fn adblock_domain_rules() {
+    let mut rules = Rules::default();
+    rules.adblock(
+        "[Adblock Plus 2.0]\n! comment\n||ads.example.com^\n\
+         @@||good.ads.example.com^\nexample.com##.banner\n||x.com^$third-party\n",
+    );
+    assert_eq!(rules.block, [".ads.example.com"]);
+    assert_eq!(rules.allow, [".good.ads.example.com"]);
+    assert_eq!(rules.skipped, 2);
+    let list = Blocklist::new(&rules).unwrap();
+    assert!(list.is_blocked("ads.example.com", "http://ads.example.com/"));
+    assert!(list.is_blocked("a.ads.example.com", "https://a.ads.example.com/"));
+    assert!(!list.is_blocked("good.ads.example.com", "http://good.ads.example.com/x"));
+    assert!(!list.is_blocked("x.com", "http://x.com/"));
+}
+
+#[test]
+fn adblock_url_rules() {
+    let mut rules = Rules::default();
+    rules.adblock(
+        "/banner/*/img^\n||cdn.example.com/ads/\n|http://start.example.com/|\n\
+         /\\/track[0-9]+\\//\n@@/banner/ok/img^\n/(/\n",
+    );
+    assert_eq!(rules.block_url.len(), 4);
+    assert_eq!(rules.allow_url.len(), 1);
+    assert_eq!(rules.skipped, 1);
+    let list = Blocklist::new(&rules).unwrap();
+    let blocked = |url: &str| list.is_blocked("example.com", url);
+    assert!(blocked("http://example.com/banner/big/img?x"));
+    assert!(blocked("http://example.com/banner/big/img"));
+    assert!(!blocked("http://example.com/banner/big/imgs"));
+    assert!(!blocked("http://example.com/banner/ok/img"));
+    assert!(blocked("https://cdn.example.com/ads/1.js"));
+    assert!(blocked("https://a.CDN.example.com/ads/1.js"));
+    assert!(!blocked("https://evilcdn.example.com/ads/1.js"));
+    assert!(!blocked("https://cdn.example.com/x/ads/1.js"));
+    assert!(blocked("http://start.example.com/"));
+    assert!(!blocked("http://start.example.com/a"));
+    assert!(blocked("http://example.com/track12/"));
+    assert!(!blocked("http://example.com/track/"));
+}
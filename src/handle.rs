@@ -1,99 +1,707 @@
-use crate::config::Config;
-use crate::http::Request;
+use crate::auth::{Auth, Denied};
+use crate::cache::{self, Directives, Entry, Lookup};
+use crate::cassette::{self, Cassette, Mode};
+use crate::config::{Config, RedirectMode};
+use crate::error::{is_timeout, Error};
+use crate::header::{self, Direction};
+use crate::http::{
+    copy_body_inspect, copy_chunked_decoded, read_head, reason, BodyLength, Request, Response,
+};
+use crate::pool::{connect, Server};
+use crate::rule::{self, Verdict};
+use crate::user::{self, Identity};
+use std::io;
 use std::io::prelude::*;
-use std::net::{TcpStream, ToSocketAddrs};
+use std::io::BufReader;
+use std::net::{Shutdown, TcpStream};
 use std::result::Result;
 use std::sync::Arc;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
-// size of buffer
-const BUFFER_LEN: usize = 131072;
-// timeout of client read operation
-const CLIENT_TIMEOUT: u64 = 200;
-// timeout of server read operation
-const SERVER_TIMEOUT: u64 = 1000;
-
-pub fn handle_client(mut stream: TcpStream, config: Arc<Config>) -> Result<(), String> {
-    let peer_ip = stream.peer_addr().unwrap().ip();
+pub fn handle_client(mut stream: TcpStream, config: Arc<Config>) -> Result<(), Error> {
+    let peer_ip = stream.peer_addr().map_err(Error::Client)?.ip();
     info!("incoming request: {}", peer_ip);
-    // block client in blacklist
-    for ip in &config.filter.ip {
-        if format!("{}", peer_ip) == *ip {
-            let strforbid =
-                b"HTTP/1.1 403 Forbidden\r\n\r\n<h1>403 Forbidden</h1> You can't use this proxy!";
-            stream
-                .write(strforbid)
-                .map_err(|e| format!("can't send 403 to client, {}", e))?;
-            return Ok(());
-        }
+    // block client in blacklist or not allowed by acl
+    if !config.filter.allow_client(peer_ip) {
+        return send_error(&mut stream, &config, 403, "You can't use this proxy!");
     }
-    // set client read timeout to make loop end at timeout
+    // client connection is closed if no request come in keep-alive timeout
     stream
-        .set_read_timeout(Some(Duration::from_millis(CLIENT_TIMEOUT)))
-        .map_err(|e| format!("can't set_read_timeout, {}", e))?;
-    // this loop won't end untill any `return` or `Err`
-    loop {
-        // buffer for massage
-        let mut req_buffer = [0u8; BUFFER_LEN];
-        // number of bytes read from stream
-        let req_bytes = stream.read(&mut req_buffer).unwrap_or(0);
-        // Sometimes it read all blank content
-        // 16 is the length of "GET / HTTP/1.1\r\n"
-        if req_bytes < 16 {
-            return Ok(());
+        .set_read_timeout(Some(Duration::from_secs(config.keep_alive.timeout)))
+        .map_err(Error::Client)?;
+    // BufReader read as much as it can from stream
+    // so that we can read header line by line without lots of system call
+    // bytes after current request are kept in it for next request
+    let mut client = BufReader::new(stream.try_clone().map_err(Error::Client)?);
+    // servers are never asked when replaying
+    let replaying = config
+        .cassette
+        .as_ref()
+        .filter(|cassette| cassette.mode == Mode::Replay);
+    // buffer for header of request
+    let mut req_buffer = Vec::new();
+    // this loop won't end untill client close connection or any `return` or `Err`
+    for count in 1.. {
+        match read_head(&mut client, &mut req_buffer) {
+            // client close connection
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            // keep-alive timeout
+            Err(ref e) if is_timeout(e) => return Ok(()),
+            Err(e) => return Err(Error::Client(e)),
+        }
+        // prase HTTP request, tell client if it is malformed
+        let parsed = Request::parse(&req_buffer).and_then(|req| {
+            req.check_scheme()?;
+            let length = req.body_length()?;
+            Ok((req, length))
+        });
+        let (mut req, length) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => return fail(&mut stream, &config, e.into()),
+        };
+        // ask for user and password if proxy needs them
+        let user = match &config.auth {
+            Some(auth) => match auth.check(&req) {
+                Ok(user) => Some(user),
+                Err(denied) => return send_auth(&mut stream, &config, auth, denied),
+            },
+            None => None,
+        };
+        if let Some(user) = &user {
+            debug!("authenticated user: {}", user);
         }
-        // prase HTTP request
-        let mut req = Request::parse(&mut req_buffer[..req_bytes])?;
+        // rules of users and groups are checked with it
+        let who = user::identify(&config.user, &config.group, user, peer_ip);
         if req.method == "CONNECT" {
-            return Ok(());
+            // tunnel is not recorded, so it can't be replayed
+            if let Some(cassette) = replaying {
+                let message = format!("{} is not in cassette", req.url());
+                return send_error(&mut stream, &config, cassette.status, &message);
+            }
+            // CONNECT take the whole connection, so we never come back to this loop
+            return tunnel(stream, client.buffer(), req, &config, &who);
         }
-        if req.method != "GET" && req.method != "POST" {
-            return Err("Invalid or not support HTTP Method".to_owned());
+        // url rules and blacklist of website
+        if !filter(&mut stream, &config, &req, &who)? {
+            return Ok(());
         }
-        // block website in blacklist
-        for website in &config.filter.website {
-            if req.host == website {
-                let strforbid =
-                        b"HTTP/1.1 451 Unavailable For Legal Reasons\r\n\r\n<h1>451 Unavailable For Legal Reasons</h1>";
-                stream
-                    .write(strforbid)
-                    .map_err(|e| format!("can't send 451 to client, {}", e))?;
-                return Ok(());
+        // modify host for website in redirection list
+        for redirect in &config.redirect {
+            if req.host.eq_ignore_ascii_case(&redirect.from) && redirect.who.is_match(&who) {
+                req.modify_host(&redirect.to);
+                // browser should know it is on another website
+                if redirect.mode == RedirectMode::Redirect {
+                    return send_redirect(&mut stream, redirect.status(), &req.url());
+                }
             }
         }
-        // modify host for website in redirection list
-        for i in 0..config.redirect.len() {
-            if req.host == config.redirect[i].from {
-                req.modify_host(&config.redirect[i].to);
+        rewrite(&config, &mut req, &who);
+        // decide it before hop-by-hop headers are removed
+        // client want to close, or client send too many requests
+        let keep_alive = req.keep_alive() && count < config.keep_alive.max;
+        req.headers.remove_hop_by_hop();
+        req.headers.remove_ignored_length();
+        header::forward_request(&config.forward, &mut req, peer_ip);
+        header::apply(
+            &config.header,
+            Direction::Request,
+            &req.host,
+            &mut req.headers,
+        );
+        // server gets the request only after the whole body is copied, so it can't answer
+        // "100 Continue" in time, proxy answers it and client sends body right away
+        if req.expect_continue() {
+            req.headers.remove("Expect");
+            if length != BodyLength::Empty {
+                stream
+                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                    .map_err(Error::Client)?;
             }
         }
         // log requset message
-        info!("GOT HTTP REQUEST, size:{} bytes", req_bytes);
+        info!("GOT HTTP REQUEST, size:{} bytes", req_buffer.len());
         trace!("{}", req);
-        // to_socket_addrs() will resole host to ip address
-        let host = format!("{}:80", req.host);
-        let host = host
-            .to_socket_addrs()
-            .map_err(|e| format!("unable to resolve host, {}", e))?
-            .next()
-            .ok_or("can't resolve host, no result found")?;
-        let mut server_stream = TcpStream::connect(&host)
-            .map_err(|e| format!("Error to connect to Server {} : {}", host, e))?;
-        req.write(&mut server_stream)
-            .map_err(|e| format!("can't send message to remote server, {}", e))?;
-        server_stream
-            .set_read_timeout(Some(Duration::from_millis(SERVER_TIMEOUT)))
-            .map_err(|e| format!("can't set_read_timeout, {}", e))?;
-        loop {
-            let mut res_buffer = [0u8; BUFFER_LEN];
-            let bytes = server_stream.read(&mut res_buffer).unwrap_or(0);
-            if bytes == 0 {
-                return Ok(());
+        if let Some(cassette) = replaying {
+            let (client, stream) = (&mut client, &mut stream);
+            match replay(&config, cassette, &req, length, keep_alive, client, stream) {
+                Ok(true) => continue,
+                Ok(false) => return Ok(()),
+                Err(e) => return fail(stream, &config, e),
             }
-            stream
-                .write(&res_buffer[..bytes])
-                .map_err(|e| format!("can't send message to client, {}", e))?;
-            info!("GOT HTTP RESPONSE,size: {} bytes", bytes);
         }
+        // fresh response in cache is sent without asking server
+        let cached = match &config.cache {
+            Some(cache) if cache::cacheable(&req, length) => cache.lookup(&req, cache::now()),
+            _ => Lookup::Miss,
+        };
+        let hit = matches!(cached, Lookup::Hit(_));
+        if !hit && config.cache.is_some() && Directives::of(&req.headers).has("only-if-cached") {
+            let message = format!("{} is not in cache", req.url());
+            return send_error(&mut stream, &config, 504, &message);
+        }
+        let forwarded = match cached {
+            Lookup::Hit(entry) => Ok(Forwarded::Cached(entry)),
+            // server checks If-None-Match before If-Modified-Since, so without ETag of stored
+            // response its 304 would be about the copy of client, let client ask server itself
+            Lookup::Stale(ref entry)
+                if entry.header("ETag").is_none() && req.headers.contains("If-None-Match") =>
+            {
+                forward(&config, &req, None, keep_alive, &mut client, &mut stream)
+            }
+            Lookup::Stale(entry) => {
+                // ask server whether stored response is modified
+                // conditions of client are checked by proxy after that,
+                // those stored response doesn't have are sent as they are
+                let mut conditional = req.clone();
+                if let Some(etag) = entry.header("ETag") {
+                    conditional.headers.insert("If-None-Match", etag);
+                }
+                if let Some(modified) = entry.header("Last-Modified") {
+                    conditional.headers.insert("If-Modified-Since", modified);
+                }
+                let stale = Some(&*entry);
+                forward(
+                    &config,
+                    &conditional,
+                    stale,
+                    keep_alive,
+                    &mut client,
+                    &mut stream,
+                )
+            }
+            Lookup::Miss => forward(&config, &req, None, keep_alive, &mut client, &mut stream),
+        };
+        let sent = match forwarded {
+            Ok(Forwarded::Sent(keep_alive)) => Ok(keep_alive),
+            Ok(Forwarded::Cached(entry)) => {
+                send_cached(&config, &req, &entry, "HIT", keep_alive, &mut stream)
+            }
+            // `req` still has validators of client, it gets 304 if its copy is the same
+            Ok(Forwarded::Revalidated(entry)) => send_cached(
+                &config,
+                &req,
+                &entry,
+                "REVALIDATED",
+                keep_alive,
+                &mut stream,
+            ),
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => return fail(&mut stream, &config, e),
+        }
+    }
+    Ok(())
+}
+
+// what is done with a request
+enum Forwarded {
+    // response of server is sent, false if client connection should be closed
+    Sent(bool),
+    // fresh response in cache should be sent
+    Cached(Arc<Entry>),
+    // server says stale response in cache is not modified, it should be sent
+    Revalidated(Arc<Entry>),
+}
+
+// send request to server and send response back to client
+// `stale` is the response in cache that request asks server about
+// `keep_alive` is whether client connection can be used again,
+// it is sent to client in Connection header of response
+fn forward(
+    config: &Config,
+    req: &Request,
+    stale: Option<&Entry>,
+    keep_alive: bool,
+    client: &mut BufReader<TcpStream>,
+    stream: &mut TcpStream,
+) -> Result<Forwarded, Error> {
+    let length = req.body_length()?;
+    let host = format!("{}:{}", req.host, req.port);
+    let cacheable = config.cache.is_some() && cache::cacheable(req, length);
+    let request_time = cache::now();
+    // exchange is written to cassette after response body is read
+    let recording = config
+        .cassette
+        .as_ref()
+        .filter(|cassette| cassette.mode == Mode::Record);
+    let mut req_body = recording.map(|_| Vec::new());
+    let mut keep = |data: &[u8]| {
+        if let Some(kept) = &mut req_body {
+            kept.extend_from_slice(data);
+        }
+    };
+    // idle connection to the same server is used again, it is put back after response
+    let (mut server_conn, reused) = match config.pool.take(&host) {
+        Some(server) => (server, true),
+        None => (Server::connect(&host)?, false),
+    };
+    let mut res_buffer = Vec::new();
+    let mut sent = server_conn.send(req, length, client, &mut res_buffer, &mut keep);
+    // server may close idle connection before we send request
+    // try again with a new connection if request has no body to send again,
+    // and server may have done it already, so only when doing it twice is harmless
+    let retry = reused && length == BodyLength::Empty && is_idempotent(req.method);
+    // a slow server is not closed, it may be doing the request
+    let closed = match &sent {
+        Ok(bytes) => *bytes == 0,
+        Err(e) => matches!(
+            e.kind(),
+            io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::BrokenPipe
+        ),
+    };
+    if retry && closed {
+        info!("keep-alive connection to {} is closed, reconnect", host);
+        server_conn = Server::connect(&host)?;
+        sent = server_conn.send(req, length, client, &mut res_buffer, &mut keep);
+    }
+    match sent {
+        Ok(0) => {
+            let e = "connection closed without response".to_owned();
+            return Err(Error::BadResponse(host, e));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(Error::server(&host, e)),
+    }
+    // whether server connection can be used again
+    let mut reuse = true;
+    // HTTP/1.0 client can't read chunked body, it is sent decoded until connection is closed
+    let mut dechunk = false;
+    // response is stored in cache after its body is read
+    let mut pending = None;
+    // response from server before proxy changes it, for cassette
+    let mut recorded = None;
+    // 1xx response is followed by another response
+    let (res, length) = loop {
+        let (mut res, length) = Response::parse(&res_buffer)
+            .and_then(|res| {
+                let length = res.body_length(req.method)?;
+                Ok((res, length))
+            })
+            .map_err(|e| Error::BadResponse(host.clone(), e.to_string()))?;
+        // Upgrade is never sent to server, so it can't switch protocols
+        if res.code == 101 {
+            return Err(Error::BadResponse(
+                host.clone(),
+                "unexpected 101".to_owned(),
+            ));
+        }
+        let last = res.code / 100 != 1;
+        if last {
+            // server tell us how long and how many times the connection can be used
+            // read them before hop-by-hop headers are removed
+            if let Some(timeout) = res.keep_alive_param("timeout") {
+                server_conn.expire = Some(Instant::now() + Duration::from_secs(timeout));
+            }
+            server_conn.remain = res
+                .keep_alive_param("max")
+                .or(server_conn.remain)
+                .map(|max| max.saturating_sub(1));
+            reuse = res.keep_alive() && length != BodyLength::Close;
+        }
+        res.headers.remove_hop_by_hop();
+        res.headers.remove_ignored_length();
+        if last && recording.is_some() {
+            let mut head = Vec::new();
+            res.write(&mut head).map_err(Error::Forward)?;
+            recorded = Some(head);
+        }
+        if let (true, Some(entry), Some(cache)) = (last && res.code == 304, stale, &config.cache) {
+            debug!("CACHE NOT MODIFIED {}", req.url());
+            if reuse {
+                config.pool.put(server_conn);
+            }
+            let entry = cache.refresh(req, entry, &res, request_time, cache::now());
+            return Ok(Forwarded::Revalidated(entry));
+        }
+        if let (true, Some(cache)) = (last, &config.cache) {
+            if cacheable {
+                pending = cache.entry(req, &res, request_time, cache::now());
+            } else if !is_safe(req.method) && res.code < 400 {
+                cache.invalidate(req);
+            }
+        }
+        header::forward_response(&config.forward, &mut res);
+        if last && cacheable {
+            res.headers.insert("X-Cache", "MISS");
+        }
+        if last {
+            dechunk = length == BodyLength::Chunked && req.version == "HTTP/1.0";
+            if dechunk {
+                res.headers.remove("Transfer-Encoding");
+            }
+            // client don't know where body ends unless connection is closed
+            if !keep_alive || length == BodyLength::Close || dechunk {
+                res.headers.insert("Connection", "close");
+            } else if req.version == "HTTP/1.0" {
+                res.headers.insert("Connection", "keep-alive");
+            }
+        }
+        header::apply(
+            &config.header,
+            Direction::Response,
+            &req.host,
+            &mut res.headers,
+        );
+        trace!("{}", res);
+        res.write(stream).map_err(Error::Client)?;
+        if last {
+            break (res, length);
+        }
+        // it is too late to send error page, a response has been sent
+        match read_head(&mut server_conn.reader, &mut res_buffer) {
+            Ok(0) => {
+                let e = io::Error::new(io::ErrorKind::UnexpectedEof, "server close connection");
+                return Err(Error::Forward(e));
+            }
+            Ok(_) => {}
+            Err(e) => return Err(Error::Forward(e)),
+        }
+    };
+    // body is kept for cache unless it is too large
+    let max_object = config.cache.as_ref().map_or(0, |cache| cache.max_object);
+    let mut body = pending.as_ref().map(|_| Vec::new());
+    let mut res_body = recorded.as_ref().map(|_| Vec::new());
+    let inspect = |data: &[u8]| {
+        if let Some(kept) = &mut body {
+            if (kept.len() + data.len()) as u64 > max_object {
+                body = None;
+            } else {
+                kept.extend_from_slice(data);
+            }
+        }
+        if let Some(kept) = &mut res_body {
+            kept.extend_from_slice(data);
+        }
+    };
+    let bytes = if dechunk {
+        copy_chunked_decoded(&mut server_conn.reader, stream, inspect)
+    } else {
+        copy_body_inspect(&mut server_conn.reader, stream, length, inspect)
+    }
+    .map_err(Error::Forward)?;
+    if let (Some(cache), Some(entry), Some(body)) = (&config.cache, pending, body) {
+        debug!("CACHE STORE {}, {} bytes", req.url(), body.len());
+        cache.store(entry, body);
     }
+    if let (Some(cassette), Some(head), Some(res_body)) = (recording, recorded, res_body) {
+        debug!("CASSETTE RECORD {} {}", req.method, req.url());
+        let req_body = req_body.unwrap_or_default();
+        cassette.record(req, &req_body, &head, length, &res_body);
+    }
+    info!(
+        "GOT HTTP RESPONSE, code: {}, header: {} bytes, body: {} bytes",
+        res.code,
+        res_buffer.len(),
+        bytes
+    );
+    if reuse {
+        config.pool.put(server_conn);
+    }
+    Ok(Forwarded::Sent(
+        keep_alive && length != BodyLength::Close && !dechunk,
+    ))
+}
+
+// send response in cache to client, or 304 if client has it
+// `label` is sent in X-Cache header
+// return false if client connection should be closed
+fn send_cached(
+    config: &Config,
+    req: &Request,
+    entry: &Entry,
+    label: &str,
+    keep_alive: bool,
+    stream: &mut TcpStream,
+) -> Result<bool, Error> {
+    let mut res = Response::parse(&entry.head)
+        .map_err(|e| Error::BadResponse(req.host.clone(), e.to_string()))?;
+    info!("CACHE {} {}, code: {}", label, req.url(), res.code);
+    let not_modified = req.not_modified(res.headers.get("ETag"), res.headers.get("Last-Modified"));
+    if not_modified {
+        // 304 has no metadata of body, see RFC 9110 section 15.4.5
+        res.code = 304;
+        res.reason = reason(304);
+        for name in &["Content-Type", "Content-Encoding", "Content-Language"] {
+            res.headers.remove(name);
+        }
+    } else {
+        res.headers
+            .insert("Content-Length", entry.body.len().to_string());
+    }
+    res.headers
+        .insert("Age", entry.age(cache::now()).to_string());
+    header::forward_response(&config.forward, &mut res);
+    if !keep_alive {
+        res.headers.insert("Connection", "close");
+    } else if req.version == "HTTP/1.0" {
+        res.headers.insert("Connection", "keep-alive");
+    }
+    res.headers.insert("X-Cache", label);
+    header::apply(
+        &config.header,
+        Direction::Response,
+        &req.host,
+        &mut res.headers,
+    );
+    trace!("{}", res);
+    let mut buf = Vec::new();
+    res.write(&mut buf).map_err(Error::Client)?;
+    if req.method != "HEAD" && !not_modified {
+        buf.extend_from_slice(&entry.body);
+    }
+    stream.write_all(&buf).map_err(Error::Client)?;
+    Ok(keep_alive)
+}
+
+// send response of request in cassette, or error page if it is not recorded
+// return false if client connection should be closed
+fn replay(
+    config: &Config,
+    cassette: &Cassette,
+    req: &Request,
+    length: BodyLength,
+    keep_alive: bool,
+    client: &mut BufReader<TcpStream>,
+    stream: &mut TcpStream,
+) -> Result<bool, Error> {
+    // body is a part of request that is matched
+    let body = cassette::read_body(client, length).map_err(Error::Client)?;
+    let recorded = match cassette.replay(req, &body) {
+        Some(recorded) => recorded,
+        None => {
+            info!("CASSETTE MISS {} {}", req.method, req.url());
+            let message = format!("{} {} is not in cassette", req.method, req.url());
+            send_error(stream, config, cassette.status, &message)?;
+            return Ok(false);
+        }
+    };
+    let mut res = Response::parse(&recorded)
+        .map_err(|e| Error::BadResponse(req.host.clone(), e.to_string()))?;
+    info!(
+        "CASSETTE REPLAY {} {}, code: {}",
+        req.method,
+        req.url(),
+        res.code
+    );
+    header::forward_response(&config.forward, &mut res);
+    if !keep_alive {
+        res.headers.insert("Connection", "close");
+    } else if req.version == "HTTP/1.0" {
+        res.headers.insert("Connection", "keep-alive");
+    }
+    header::apply(
+        &config.header,
+        Direction::Response,
+        &req.host,
+        &mut res.headers,
+    );
+    trace!("{}", res);
+    let mut buf = Vec::new();
+    res.write(&mut buf).map_err(Error::Client)?;
+    stream.write_all(&buf).map_err(Error::Client)?;
+    Ok(keep_alive)
+}
+
+// method that doesn't change anything on server
+fn is_safe(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE")
+}
+
+// sending it twice is the same as once, see RFC 9110 section 9.2.2
+fn is_idempotent(method: &str) -> bool {
+    is_safe(method) || matches!(method, "PUT" | "DELETE")
+}
+
+// HTTPS use CONNECT method to ask proxy to open a TCP tunnel
+// "CONNECT www.example.com:443 HTTP/1.1"
+// After we reply 200, proxy just copy bytes between client and server,
+// the encrypted TLS traffic is not our business.
+fn tunnel(
+    mut stream: TcpStream,
+    buffered: &[u8],
+    mut req: Request,
+    config: &Config,
+    who: &Identity,
+) -> Result<(), Error> {
+    // request target of CONNECT is authority: "host:port"
+    // url rules and blacklist of website
+    if !filter(&mut stream, config, &req, who)? {
+        return Ok(());
+    }
+    // redirection list also work for HTTPS
+    // browser don't follow 3xx response of CONNECT, so redirect mode is the same as rewrite
+    for redirect in &config.redirect {
+        if req.host.eq_ignore_ascii_case(&redirect.from) && redirect.who.is_match(who) {
+            req.modify_host(&redirect.to);
+        }
+    }
+    rewrite(config, &mut req, who);
+    info!("GOT CONNECT REQUEST, {}:{}", req.host, req.port);
+    trace!("{}", req);
+    let mut server_stream = match connect(&format!("{}:{}", req.host, req.port)) {
+        Ok(server_stream) => server_stream,
+        Err(e) => return fail(&mut stream, config, e),
+    };
+    stream
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .map_err(Error::Client)?;
+    // client may send TLS ClientHello together with CONNECT
+    if !buffered.is_empty() {
+        server_stream.write_all(buffered).map_err(Error::Forward)?;
+    }
+    // TLS connection may idle for a long time, so remove timeout
+    stream.set_read_timeout(None).map_err(Error::Client)?;
+    // TcpStream is just a file descriptor, try_clone() make another handle
+    // so that we can read and write it in two threads at the same time
+    let mut client_read = stream.try_clone().map_err(Error::Client)?;
+    let mut server_write = server_stream.try_clone().map_err(Error::Forward)?;
+    // client -> server in another thread
+    let upload = thread::spawn(move || {
+        let bytes = io::copy(&mut client_read, &mut server_write);
+        // tell server that client won't send anything
+        let _ = server_write.shutdown(Shutdown::Write);
+        bytes
+    });
+    // server -> client in this thread
+    let download = io::copy(&mut server_stream, &mut stream);
+    // tell client that server won't send anything
+    let _ = stream.shutdown(Shutdown::Write);
+    let upload = upload
+        .join()
+        .map_err(|_| Error::Forward(io::Error::other("tunnel thread panicked")))?;
+    info!(
+        "CONNECT TUNNEL CLOSED, upload: {} bytes, download: {} bytes",
+        upload.unwrap_or(0),
+        download.unwrap_or(0)
+    );
+    Ok(())
+}
+
+// check url rules, then blacklist of host and url
+// return false if proxy has answered the request
+fn filter(
+    stream: &mut TcpStream,
+    config: &Config,
+    req: &Request,
+    who: &Identity,
+) -> Result<bool, Error> {
+    match rule::check(&config.filter.rule, req, who) {
+        Verdict::Allow => return Ok(true),
+        Verdict::Deny(code) => {
+            let message = format!("{} is denied by proxy", req.url());
+            send_error(stream, config, code, &message)?;
+            return Ok(false);
+        }
+        Verdict::Redirect(location) => {
+            send_redirect(stream, 302, location)?;
+            return Ok(false);
+        }
+        Verdict::Continue => {}
+    }
+    if config.filter.is_blocked(&req.host, &req.url(), who) {
+        let message = format!("{} is blocked by proxy", req.host);
+        send_error(stream, config, 451, &message)?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// the first matched rewrite rule changes url of request
+fn rewrite(config: &Config, req: &mut Request, who: &Identity) {
+    for rewrite in config
+        .rewrite
+        .iter()
+        .filter(|rewrite| rewrite.who.is_match(who))
+    {
+        let regex = match &rewrite.regex {
+            Some(regex) => regex,
+            None => continue,
+        };
+        match req.rewrite(regex, &rewrite.replace) {
+            Ok(false) => continue,
+            Ok(true) => info!("REWRITE TO {}", req.url()),
+            // request is not changed if result is not a valid url
+            Err(e) => warn!("rewrite {} of {} failed, {}", rewrite.pattern, req.url(), e),
+        }
+        return;
+    }
+}
+
+// tell client what is wrong if it is not too late, then close connection
+fn fail(stream: &mut TcpStream, config: &Config, e: Error) -> Result<(), Error> {
+    if let Some(code) = e.status() {
+        // error of sending error page is not important
+        let _ = send_error(stream, config, code, &e.to_string());
+    }
+    Err(e)
+}
+
+// response created by proxy, client connection is closed after it
+fn send_error(
+    stream: &mut TcpStream,
+    config: &Config,
+    code: u16,
+    message: &str,
+) -> Result<(), Error> {
+    send_page(stream, config, code, message, "")
+}
+
+// error page with extra header lines, each one ends with "\r\n"
+fn send_page(
+    stream: &mut TcpStream,
+    config: &Config,
+    code: u16,
+    message: &str,
+    headers: &str,
+) -> Result<(), Error> {
+    let reason = reason(code);
+    let body = config.error_page.render(code, reason, message);
+    // write!() to TcpStream send every piece in a packet, so format it first
+    let res = format!(
+        "HTTP/1.1 {} {}\r\n{}Content-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        headers,
+        body.len(),
+        body
+    );
+    stream.write_all(res.as_bytes()).map_err(Error::Client)
+}
+
+// ask client for user and password
+fn send_auth(
+    stream: &mut TcpStream,
+    config: &Config,
+    auth: &Auth,
+    denied: Denied,
+) -> Result<(), Error> {
+    let headers: String = auth
+        .challenges(denied == Denied::Stale)
+        .iter()
+        .map(|challenge| format!("Proxy-Authenticate: {}\r\n", challenge))
+        .collect();
+    let message = match denied {
+        Denied::Missing => "This proxy needs user and password",
+        Denied::Invalid => "Wrong user or password",
+        Denied::Stale => "Authentication is expired",
+    };
+    send_page(stream, config, 407, message, &headers)
+}
+
+// redirection created by proxy, client connection is closed after it
+fn send_redirect(stream: &mut TcpStream, code: u16, location: &str) -> Result<(), Error> {
+    let res = format!(
+        "HTTP/1.1 {} {}\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        code,
+        reason(code),
+        location
+    );
+    stream.write_all(res.as_bytes()).map_err(Error::Client)
 }
@@ -0,0 +1,372 @@
+use crate::config::Forward;
+use crate::filter::DomainMatcher;
+use crate::http::{is_token, Headers, Request, Response};
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+// policy of header in config.toml, applied in order
+// [[header]]
+// host=".example.com"
+// direction="response"
+// action="replace"
+// name="Set-Cookie"
+// pattern="; *Secure"
+// value=""
+#[derive(Deserialize)]
+pub struct HeaderRule {
+    // syntax of DomainMatcher, every host if it is not given
+    pub host: Option<String>,
+    #[serde(default)]
+    pub direction: Direction,
+    pub action: HeaderAction,
+    pub name: String,
+    // value of set and append, replacement of replace ("$1" is a capture group)
+    pub value: Option<String>,
+    // new name of rename
+    pub to: Option<String>,
+    // regex of replace
+    pub pattern: Option<String>,
+    // host and pattern above, built when config is opened
+    #[serde(skip)]
+    host_matcher: Option<DomainMatcher>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    // headers sent to server
+    #[default]
+    Request,
+    // headers sent back to client
+    Response,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderAction {
+    // replace all headers of this name with one
+    Set,
+    // add another one, keep the old ones
+    Append,
+    Remove,
+    Rename,
+    // regex replace in value
+    Replace,
+}
+
+// headers that decide how messages are sent, they are done before rules are applied
+const FRAMING: [&str; 4] = ["Content-Length", "Transfer-Encoding", "Connection", "Host"];
+
+fn is_framing(name: &str) -> bool {
+    FRAMING.iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+// always applied before rules in config.toml, a later rule can set the header again
+pub fn default_rules() -> Vec<HeaderRule> {
+    vec![HeaderRule {
+        // prevent upgrade HTTP to HTTPS
+        host: None,
+        direction: Direction::Request,
+        action: HeaderAction::Remove,
+        name: "Upgrade-Insecure-Requests".to_owned(),
+        value: None,
+        to: None,
+        pattern: None,
+        host_matcher: None,
+        regex: None,
+    }]
+}
+
+impl HeaderRule {
+    // check and compile rule, error message is shown when config is opened
+    pub fn load(&mut self) -> Result<(), String> {
+        if !is_token(&self.name) {
+            return Err(format!("invalid header name {:?}", self.name));
+        }
+        // changing them after message length is known would break the connection
+        if is_framing(&self.name) {
+            return Err(format!("header {} can't be changed", self.name));
+        }
+        if let Some(host) = &self.host {
+            let matcher = DomainMatcher::new(&[host]).map_err(|e| e.to_string())?;
+            self.host_matcher = Some(matcher);
+        }
+        // "\r\n" in value would start another header
+        if let Some(value) = &self.value {
+            if value.contains(['\r', '\n']) {
+                return Err(format!("invalid header value {:?}", value));
+            }
+        }
+        match self.action {
+            HeaderAction::Set | HeaderAction::Append if self.value.is_none() => {
+                Err(format!("{:?} {} without value", self.action, self.name))
+            }
+            HeaderAction::Rename => match &self.to {
+                Some(to) if is_framing(to) => Err(format!("header {} can't be changed", to)),
+                Some(to) if is_token(to) => Ok(()),
+                Some(to) => Err(format!("invalid header name {:?}", to)),
+                None => Err(format!("rename {} without to", self.name)),
+            },
+            HeaderAction::Replace => match (&self.pattern, &self.value) {
+                (Some(pattern), Some(_)) => {
+                    self.regex = Some(Regex::new(pattern).map_err(|e| e.to_string())?);
+                    Ok(())
+                }
+                _ => Err(format!("replace {} without pattern or value", self.name)),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, headers: &mut Headers) {
+        let value = self.value.as_ref().map_or("", |x| &x[..]);
+        match self.action {
+            HeaderAction::Set => headers.insert(self.name.clone(), value.to_owned()),
+            HeaderAction::Append => headers.append(self.name.clone(), value.to_owned()),
+            HeaderAction::Remove => {
+                headers.remove(&self.name);
+            }
+            HeaderAction::Rename => {
+                let to = self.to.as_ref().map_or("", |x| &x[..]);
+                headers.rename(&self.name, to.to_owned());
+            }
+            HeaderAction::Replace => {
+                if let Some(regex) = &self.regex {
+                    // Borrowed means nothing is replaced
+                    headers.modify(&self.name, |old| {
+                        match regex.replace_all(old, value.as_bytes()) {
+                            Cow::Owned(new) => Some(new),
+                            Cow::Borrowed(_) => None,
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
+
+// apply rules of this direction and host in order
+pub fn apply(rules: &[HeaderRule], direction: Direction, host: &str, headers: &mut Headers) {
+    for rule in rules {
+        let host_match = rule
+            .host_matcher
+            .as_ref()
+            .is_none_or(|matcher| matcher.is_match(host));
+        if rule.direction == direction && host_match {
+            rule.apply(headers);
+        }
+    }
+}
+
+// "Via: 1.1 proxy", protocol is "1.1" of "HTTP/1.1"
+fn add_via(config: &Forward, headers: &mut Headers, version: &str) {
+    let protocol = version.strip_prefix("HTTP/").unwrap_or(version);
+    headers.append("Via", format!("{} {}", protocol, config.via));
+}
+
+// tell server that request pass through proxy, or hide it in anonymous mode
+pub fn forward_request(config: &Forward, req: &mut Request, client: IpAddr) {
+    if config.anonymous {
+        // headers of proxies before us tell who the client is
+        for name in &["Via", "X-Forwarded-For", "Forwarded", "X-Real-IP"] {
+            req.headers.remove(name);
+        }
+        return;
+    }
+    add_via(config, &mut req.headers, req.version);
+    if !config.forwarded {
+        return;
+    }
+    // "X-Forwarded-For: client, proxy1, proxy2"
+    let xff = match req.headers.get("X-Forwarded-For") {
+        Some(old) => format!("{}, {}", String::from_utf8_lossy(old), client),
+        None => client.to_string(),
+    };
+    req.headers.insert("X-Forwarded-For", xff);
+    // "Forwarded: for=192.0.2.1;proto=http", IPv6 is quoted, see RFC 7239
+    let node = match client {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    let proto = req.path.scheme().unwrap_or("http").to_ascii_lowercase();
+    let element = format!("for={};proto={}", node, proto);
+    let forwarded = match req.headers.get("Forwarded") {
+        Some(old) => format!("{}, {}", String::from_utf8_lossy(old), element),
+        None => element,
+    };
+    req.headers.insert("Forwarded", forwarded);
+}
+
+// tell client that response pass through proxy
+pub fn forward_response(config: &Forward, res: &mut Response) {
+    if !config.anonymous {
+        add_via(config, &mut res.headers, res.version);
+    }
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn rules(toml: &str) -> Vec<HeaderRule> {
+    crate::config::test_rules(toml, HeaderRule::load).unwrap()
+}
+
+#[cfg(test)]
+fn headers(list: &[(&'static str, &'static str)]) -> Headers<'static> {
+    let mut headers = Headers::new();
+    for (key, value) in list {
+        headers.append(*key, *value);
+    }
+    headers
+}
+
+#[test]
+fn header_default_rule() {
+    let mut rules = default_rules();
+    rules[0].load().unwrap();
+    let mut h = headers(&[("Host", "a.test"), ("upgrade-insecure-requests", "1")]);
+    apply(&rules, Direction::Response, "a.test", &mut h);
+    assert_eq!(h.len(), 2);
+    apply(&rules, Direction::Request, "a.test", &mut h);
+    assert_eq!(h.to_string(), "Host: a.test\r\n");
+}
+
+#[test]
+fn header_actions() {
+    let rules = rules(
+        r#"
+        [[header]]
+        action = "set"
+        name = "X-Proxy"
+        value = "on"
+        [[header]]
+        action = "append"
+        name = "Via"
+        value = "1.1 proxy"
+        [[header]]
+        action = "remove"
+        name = "cookie"
+        [[header]]
+        action = "rename"
+        name = "X-Old"
+        to = "X-New"
+        [[header]]
+        action = "replace"
+        name = "User-Agent"
+        pattern = "Chrome/([0-9]+)"
+        value = "Browser/$1"
+        "#,
+    );
+    let mut h = headers(&[
+        ("X-Proxy", "1"),
+        ("Via", "1.0 other"),
+        ("Cookie", "a=1"),
+        ("X-Old", "x"),
+        ("x-proxy", "2"),
+        ("User-Agent", "Mozilla/5.0 Chrome/120.0"),
+    ]);
+    apply(&rules, Direction::Request, "a.test", &mut h);
+    assert_eq!(
+        h.to_string(),
+        "X-Proxy: on\r\nVia: 1.0 other\r\nX-New: x\r\n\
+         User-Agent: Mozilla/5.0 Browser/120.0\r\nVia: 1.1 proxy\r\n"
+    );
+}
+
+#[test]
+fn header_host_and_direction() {
+    let rules = rules(
+        r#"
+        [[header]]
+        host = ".example.com"
+        direction = "response"
+        action = "remove"
+        name = "Server"
+        "#,
+    );
+    let mut h = headers(&[("Server", "nginx")]);
+    apply(&rules, Direction::Request, "www.example.com", &mut h);
+    apply(&rules, Direction::Response, "example.org", &mut h);
+    assert_eq!(h.len(), 1);
+    apply(&rules, Direction::Response, "www.example.com", &mut h);
+    assert_eq!(h.len(), 0);
+}
+
+#[test]
+fn header_invalid() {
+    let load = |toml: &str| crate::config::test_rules(toml, HeaderRule::load);
+    assert!(load("[[header]]\naction = \"set\"\nname = \"X-A\"").is_err());
+    assert!(load("[[header]]\naction = \"set\"\nname = \"X A\"\nvalue = \"1\"").is_err());
+    assert!(
+        load("[[header]]\naction = \"set\"\nname = \"X-A\"\nvalue = \"1\\r\\nX-B: 2\"").is_err()
+    );
+    assert!(load("[[header]]\naction = \"rename\"\nname = \"X-A\"").is_err());
+    assert!(load("[[header]]\naction = \"rename\"\nname = \"X-A\"\nto = \"X:B\"").is_err());
+    assert!(load("[[header]]\naction = \"replace\"\nname = \"X-A\"\nvalue = \"1\"").is_err());
+    assert!(load(
+        "[[header]]\naction = \"replace\"\nname = \"X-A\"\npattern = \"(\"\nvalue = \"\""
+    )
+    .is_err());
+    assert!(load("[[header]]\naction = \"remove\"\nname = \"X-A\"").is_ok());
+    // framing headers are decided before rules
+    assert!(load("[[header]]\naction = \"remove\"\nname = \"content-length\"").is_err());
+    assert!(load("[[header]]\naction = \"set\"\nname = \"Host\"\nvalue = \"b\"").is_err());
+    assert!(load("[[header]]\naction = \"rename\"\nname = \"X-A\"\nto = \"Connection\"").is_err());
+    let te = "[[header]]\naction = \"append\"\nname = \"Transfer-Encoding\"\nvalue = \"gzip\"";
+    assert!(load(te).is_err());
+}
+
+#[test]
+fn forward_headers() {
+    let config = Forward {
+        via: "test-proxy".to_owned(),
+        forwarded: true,
+        anonymous: false,
+    };
+    let buf = b"GET http://a/ HTTP/1.1\r\nHost: a\r\nX-Forwarded-For: 10.0.0.1\r\n\r\n";
+    let mut req = Request::parse(buf).unwrap();
+    forward_request(&config, &mut req, "192.0.2.1".parse().unwrap());
+    assert_eq!(
+        req.headers.to_string(),
+        "Host: a\r\nX-Forwarded-For: 10.0.0.1, 192.0.2.1\r\nVia: 1.1 test-proxy\r\n\
+         Forwarded: for=192.0.2.1;proto=http\r\n"
+    );
+    let mut req = Request::parse(b"GET / HTTP/1.0\r\nHost: a\r\nForwarded: for=x\r\n\r\n").unwrap();
+    forward_request(&config, &mut req, "2001:db8::1".parse().unwrap());
+    assert_eq!(req.headers.get("Via"), Some(&b"1.0 test-proxy"[..]));
+    assert_eq!(
+        req.headers.get("X-Forwarded-For"),
+        Some(&b"2001:db8::1"[..])
+    );
+    assert_eq!(
+        req.headers.get("Forwarded"),
+        Some(&b"for=x, for=\"[2001:db8::1]\";proto=http"[..])
+    );
+    let mut res = Response::parse(b"HTTP/1.1 200 OK\r\nVia: 1.1 cdn\r\n\r\n").unwrap();
+    forward_response(&config, &mut res);
+    assert_eq!(
+        res.headers.to_string(),
+        "Via: 1.1 cdn\r\nVia: 1.1 test-proxy\r\n"
+    );
+}
+
+#[test]
+fn forward_anonymous() {
+    let config = Forward {
+        via: "test-proxy".to_owned(),
+        forwarded: true,
+        anonymous: true,
+    };
+    let buf =
+        b"GET http://a/ HTTP/1.1\r\nHost: a\r\nVia: 1.1 other\r\nX-Forwarded-For: 10.0.0.1\r\n\
+                Forwarded: for=10.0.0.1\r\nX-Real-IP: 10.0.0.1\r\n\r\n";
+    let mut req = Request::parse(buf).unwrap();
+    forward_request(&config, &mut req, "192.0.2.1".parse().unwrap());
+    assert_eq!(req.headers.to_string(), "Host: a\r\n");
+    let mut res = Response::parse(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+    forward_response(&config, &mut res);
+    assert!(res.headers.is_empty());
+}
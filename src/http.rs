@@ -1,35 +1,496 @@
+use regex::Regex;
+use std::borrow::Cow;
 use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::ops::{Deref, Index};
 // A module to parse HTTP request
 
+// max size of request or response header
+const MAX_HEAD_LEN: usize = 65536;
+
 // HTTP Header
+#[derive(Clone)]
 pub struct Header<'a> {
     // In Rust, str is a slice of String
     // String is valid UTF-8
-    key: &'a str,
-
-    // colon storage ": "
-    colon: &'a str,
+    // Cow is a slice of buffer, or a String after we modify it
+    key: Cow<'a, str>,
 
     // Notice that specification allows for values that may not be
     // valid ASCII, nor UTF-8
-    value: &'a [u8],
+    value: HeaderValue<'a>,
+}
+
+impl<'a> Header<'a> {
+    // name of header, in its original case
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+// value of header, borrowed from buffer or owned after modified
+// it is just a Cow<[u8]> that can be compared with b"value"
+#[derive(Clone, Debug)]
+pub struct HeaderValue<'a>(Cow<'a, [u8]>);
+
+impl<'a> Deref for HeaderValue<'a> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b [u8]> for HeaderValue<'a> {
+    fn eq(&self, other: &&'b [u8]) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl<'a, 'b, const N: usize> PartialEq<&'b [u8; N]> for HeaderValue<'a> {
+    fn eq(&self, other: &&'b [u8; N]) -> bool {
+        *self.0 == other[..]
+    }
+}
+
+impl<'a> From<&'a [u8]> for HeaderValue<'a> {
+    fn from(value: &'a [u8]) -> HeaderValue<'a> {
+        HeaderValue(Cow::Borrowed(value))
+    }
+}
+
+impl<'a> From<&'a str> for HeaderValue<'a> {
+    fn from(value: &'a str) -> HeaderValue<'a> {
+        HeaderValue(Cow::Borrowed(value.as_bytes()))
+    }
+}
+
+impl<'a> From<Vec<u8>> for HeaderValue<'a> {
+    fn from(value: Vec<u8>) -> HeaderValue<'a> {
+        HeaderValue(Cow::Owned(value))
+    }
+}
+
+impl<'a> From<String> for HeaderValue<'a> {
+    fn from(value: String) -> HeaderValue<'a> {
+        HeaderValue(Cow::Owned(value.into_bytes()))
+    }
+}
+
+// All headers of a message
+// A HashMap would lose the order and the case of header names,
+// and some headers like Set-Cookie can appear more than once,
+// so it is a Vec, and names are compared case-insensitively when searching.
+#[derive(Default, Clone)]
+pub struct Headers<'a> {
+    list: Vec<Header<'a>>,
+}
+
+impl<'a> Headers<'a> {
+    pub fn new() -> Headers<'a> {
+        Headers { list: Vec::new() }
+    }
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, Header<'a>> {
+        self.list.iter()
+    }
+    // value of the first header with this name
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.position(key).map(|pos| &self.list[pos].value[..])
+    }
+    // values of all headers with this name, in order
+    pub fn get_all<'b>(&'b self, key: &'b str) -> impl Iterator<Item = &'b [u8]> + 'b {
+        self.list
+            .iter()
+            .filter(move |header| header.key.eq_ignore_ascii_case(key))
+            .map(|header| &header.value[..])
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+    // replace value of the first header with this name and remove the others
+    // or append it if there is no such header
+    pub fn insert<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<Cow<'a, str>>,
+        V: Into<HeaderValue<'a>>,
+    {
+        let key = key.into();
+        match self.position(&key) {
+            Some(pos) => {
+                self.list[pos].value = value.into();
+                // keep the first one only
+                let mut index = 0;
+                self.list.retain(|header| {
+                    index += 1;
+                    index - 1 <= pos || !header.key.eq_ignore_ascii_case(&key)
+                });
+            }
+            None => self.append(key, value),
+        }
+    }
+    // add header at the end, even if header with the same name exists
+    pub fn append<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<Cow<'a, str>>,
+        V: Into<HeaderValue<'a>>,
+    {
+        self.list.push(Header {
+            key: key.into(),
+            value: value.into(),
+        });
+    }
+    // remove all headers with this name, return how many are removed
+    pub fn remove(&mut self, key: &str) -> usize {
+        let len = self.list.len();
+        self.list
+            .retain(|header| !header.key.eq_ignore_ascii_case(key));
+        len - self.list.len()
+    }
+    // give all headers with this name a new name, return how many are renamed
+    pub fn rename<K>(&mut self, key: &str, to: K) -> usize
+    where
+        K: Into<Cow<'a, str>>,
+    {
+        let to = to.into();
+        let mut count = 0;
+        for header in &mut self.list {
+            if header.key.eq_ignore_ascii_case(key) {
+                header.key = to.clone();
+                count += 1;
+            }
+        }
+        count
+    }
+    // change values of all headers with this name, `f` return None to keep a value
+    // return how many are changed
+    pub fn modify<F>(&mut self, key: &str, mut f: F) -> usize
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>>,
+    {
+        let mut count = 0;
+        for header in &mut self.list {
+            if header.key.eq_ignore_ascii_case(key) {
+                if let Some(value) = f(&header.value) {
+                    header.value = value.into();
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+    // Content-Length is ignored when Transfer-Encoding is given, and it must not be sent,
+    // or the next one may find another end of body, see RFC 9112 section 6.3
+    pub fn remove_ignored_length(&mut self) {
+        if self.contains("Transfer-Encoding") {
+            self.remove("Content-Length");
+        }
+    }
+    // remove headers that only make sense on one connection, see RFC 7230 section 6.1
+    // Transfer-Encoding is hop-by-hop too, but proxy keeps chunked body as chunked,
+    // so it is kept with the body
+    pub fn remove_hop_by_hop(&mut self) {
+        // headers listed in Connection, "Connection: close, X-Foo"
+        let listed: Vec<String> = self
+            .get_all("Connection")
+            .flat_map(|value| value.split(|&c| c == b','))
+            .filter_map(|name| std::str::from_utf8(trim_both(name)).ok())
+            .map(|name| name.to_owned())
+            .collect();
+        for name in &listed {
+            // never let client remove headers that decide where the message ends
+            let framing = ["Content-Length", "Transfer-Encoding", "Host"];
+            if !framing.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+                self.remove(name);
+            }
+        }
+        for name in &[
+            "Connection",
+            "Keep-Alive",
+            "Proxy-Connection",
+            "Proxy-Authenticate",
+            "Proxy-Authorization",
+            "TE",
+            "Trailer",
+            "Upgrade",
+        ] {
+            self.remove(name);
+        }
+    }
+    fn position(&self, key: &str) -> Option<usize> {
+        self.list
+            .iter()
+            .position(|header| header.key.eq_ignore_ascii_case(key))
+    }
+    // "Name: value\r\n" for every header
+    pub fn write<T: Write>(&self, f: &mut T) -> io::Result<()> {
+        for header in &self.list {
+            write!(f, "{}: ", header.key)?;
+            f.write_all(&header.value)?;
+            f.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Index<usize> for Headers<'a> {
+    type Output = Header<'a>;
+    fn index(&self, index: usize) -> &Header<'a> {
+        &self.list[index]
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b Headers<'a> {
+    type Item = &'b Header<'a>;
+    type IntoIter = std::slice::Iter<'b, Header<'a>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
 }
 
+// log headers, value may not be valid UTF-8
+impl<'a> fmt::Display for Headers<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for header in &self.list {
+            write!(f, "{}: ", header.key)?;
+            if let Ok(value_str) = std::str::from_utf8(&header.value) {
+                write!(f, "{}\r\n", value_str)?;
+            } else {
+                write!(f, "[invalid utf8 key]\r\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Request target, see RFC 7230 section 5.3
+// "/where?q=now"                          origin-form
+// "http://www.example.org/pub/WWW/"       absolute-form
+// "www.example.com:80"                    authority-form, only for CONNECT
+// "*"                                     asterisk-form, only for OPTIONS
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UriForm {
+    Origin,
+    Absolute,
+    Authority,
+    Asterisk,
+}
+
+#[derive(Clone, Debug)]
+pub struct Uri {
+    form: UriForm,
+    // "http" or "https", only for absolute-form
+    scheme: Option<String>,
+    // "user:password" before "@", only for absolute-form
+    userinfo: Option<String>,
+    // IPv6 address keep its "[]", so that "host:port" is still valid
+    host: Option<String>,
+    // only if it is written in uri, default port is not filled
+    port: Option<u16>,
+    // may be empty for "http://example.org"
+    path: String,
+    // after "?"
+    query: Option<String>,
+}
+
+// not every part is used by the proxy itself
+#[allow(dead_code)]
+impl Uri {
+    // CONNECT is the only method that use authority-form
+    pub fn parse(target: &str, connect: bool) -> Result<Uri, ParseError> {
+        let mut uri = Uri {
+            form: UriForm::Origin,
+            scheme: None,
+            userinfo: None,
+            host: None,
+            port: None,
+            path: String::new(),
+            query: None,
+        };
+        if connect {
+            uri.form = UriForm::Authority;
+            uri.set_authority(target)?;
+            return Ok(uri);
+        }
+        if target == "*" {
+            uri.form = UriForm::Asterisk;
+            return Ok(uri);
+        }
+        // absolute-form has a scheme followed by "://"
+        // anything else is taken as origin-form, even if it is not start with "/"
+        let mut rest = target;
+        if let Some(pos) = target.find("://") {
+            let scheme = &target[..pos];
+            let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+            if valid_scheme {
+                uri.form = UriForm::Absolute;
+                uri.scheme = Some(scheme.to_owned());
+                rest = &target[pos + 3..];
+                // authority ends at path or query
+                let end = rest.find(['/', '?']).unwrap_or(rest.len());
+                let mut authority = &rest[..end];
+                if let Some(at) = authority.rfind('@') {
+                    uri.userinfo = Some(authority[..at].to_owned());
+                    authority = &authority[at + 1..];
+                }
+                uri.set_authority(authority)?;
+                rest = &rest[end..];
+            }
+        }
+        match rest.find('?') {
+            Some(pos) => {
+                uri.path = rest[..pos].to_owned();
+                uri.query = Some(rest[pos + 1..].to_owned());
+            }
+            None => uri.path = rest.to_owned(),
+        }
+        Ok(uri)
+    }
+
+    fn set_authority(&mut self, authority: &str) -> Result<(), ParseError> {
+        let (host, port) = split_authority(authority).ok_or(ParseError::Uri)?;
+        self.host = Some(host.to_owned());
+        self.port = port;
+        Ok(())
+    }
+
+    pub fn form(&self) -> UriForm {
+        self.form
+    }
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_ref().map(|x| &x[..])
+    }
+    pub fn userinfo(&self) -> Option<&str> {
+        self.userinfo.as_ref().map(|x| &x[..])
+    }
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_ref().map(|x| &x[..])
+    }
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_ref().map(|x| &x[..])
+    }
+    // only absolute-form and authority-form have host
+    pub fn set_host(&mut self, host: &str) {
+        if self.host.is_some() {
+            self.host = Some(host.to_owned());
+        }
+    }
+    pub fn set_port(&mut self, port: Option<u16>) {
+        if self.host.is_some() {
+            self.port = port;
+        }
+    }
+    pub fn set_path(&mut self, path: &str) {
+        self.path = path.to_owned();
+    }
+    pub fn set_query(&mut self, query: Option<&str>) {
+        self.query = query.map(|x| x.to_owned());
+    }
+}
+
+// write uri as it is in request line
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.form == UriForm::Asterisk {
+            return write!(f, "*");
+        }
+        if let Some(ref scheme) = self.scheme {
+            write!(f, "{}://", scheme)?;
+        }
+        if let Some(ref userinfo) = self.userinfo {
+            write!(f, "{}@", userinfo)?;
+        }
+        if let Some(ref host) = self.host {
+            write!(f, "{}", host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(ref query) = self.query {
+            write!(f, "?{}", query)?;
+        }
+        Ok(())
+    }
+}
+
+// "host", "host:port", "[::1]:port"
+// None if port is not a number
+pub fn split_authority(authority: &str) -> Option<(&str, Option<u16>)> {
+    // rfind because IPv6 address "[::1]:443" also contains ':'
+    let (host, port) = match authority.rfind(':') {
+        Some(pos) if !authority[pos..].contains(']') => {
+            (&authority[..pos], Some(&authority[pos + 1..]))
+        }
+        _ => (authority, None),
+    };
+    match port {
+        // "host:" is allowed, port is just not given
+        Some("") | None => Some((host, None)),
+        Some(port) if port.bytes().all(|c| c.is_ascii_digit()) => {
+            Some((host, Some(port.parse().ok()?)))
+        }
+        Some(_) => None,
+    }
+}
+
+// so that we can write `req.path == "/index.html"`
+impl<'b> PartialEq<&'b str> for Uri {
+    fn eq(&self, other: &&'b str) -> bool {
+        // compare piece by piece while formatting, without building a String
+        struct Compare<'s> {
+            rest: &'s str,
+        }
+        impl<'s> fmt::Write for Compare<'s> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                if !self.rest.starts_with(s) {
+                    return Err(fmt::Error);
+                }
+                self.rest = &self.rest[s.len()..];
+                Ok(())
+            }
+        }
+        let mut compare = Compare { rest: other };
+        fmt::write(&mut compare, format_args!("{}", self)).is_ok() && compare.rest.is_empty()
+    }
+}
+
+#[derive(Clone)]
 pub struct Request<'a> {
-    // "GET" or "POST"
+    // "GET", "POST", "PUT" ... any token is allowed
     pub method: &'a str,
 
-    // path is String,so that it can be modified
-    pub path: String,
+    // request target, parsed so that host and path can be modified
+    pub path: Uri,
 
     // "HTTP/1.1"
     pub version: &'a str,
 
-    // TODO: maybe HashMap is better
-    pub headers: Vec<Header<'a>>,
+    pub headers: Headers<'a>,
 
     // easy way to get host without searching headers
-    pub host: &'a str,
+    // it is host in absolute url, or host in "Host" header, without port
+    pub host: String,
+
+    // port to connect, default port of scheme if not given
+    pub port: u16,
 
     // may not be valid UTF-8
     pub body: &'a [u8],
@@ -37,32 +498,89 @@ pub struct Request<'a> {
 
 impl<'a> Request<'a> {
     // replace host and url with another host
-    pub fn modify_host(&mut self, host: &'a str) {
-        // skip "/" in "http://" and find next /
-        // we use it as start index of path
-        let url = self.path[7..].find("/");
-        match url {
-            Some(url) => {
-                self.path = format!("http://{}/{}", host, &self.path[url + 7..]);
-            }
-            // None show that url dont have "/" after "http://""
-            None => {
-                self.path = format!("http://{}/", host);
-            }
+    // host may be "host:port", port is kept if it is not given
+    pub fn modify_host(&mut self, host: &str) {
+        let (host, port) = match split_authority(host) {
+            Some((host, port)) => (host, port),
+            None => (host, None),
+        };
+        // only absolute-form "http://host/path" and authority-form contain host
+        // "/path" and "*" of "OPTIONS * HTTP/1.1" are kept as it is
+        self.path.set_host(host);
+        if let Some(port) = port {
+            self.port = port;
+            self.path.set_port(Some(port));
         }
 
         // this program use self.host to crate request
         // not self.headers["Host"]
         // buf we still need to change "Host" in headers
         // if not so,web server will send 400 back
+        self.host = host.to_owned();
+        self.update_host_header();
+    }
+    // "Host" header has port only if it is not the default one
+    fn update_host_header(&mut self) {
+        let host = if self.port == default_port(self.path.scheme()) {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+        self.headers.insert("Host", host);
+    }
+    // replace host, port, path and query with those in an absolute url
+    // request target keeps its form, "/path" is still "/path" with a new "Host" header
+    pub fn set_url(&mut self, url: &str) -> Result<(), ParseError> {
+        let uri = Uri::parse(url, false)?;
+        let host = match (uri.form(), uri.host()) {
+            (UriForm::Absolute, Some(host)) => host.to_owned(),
+            _ => return Err(ParseError::Uri),
+        };
+        // tunnel of CONNECT doesn't care, other requests are sent in plain text
+        if self.path.form() != UriForm::Authority && !is_http(uri.scheme()) {
+            return Err(ParseError::Scheme);
+        }
+        self.port = uri.port().unwrap_or_else(|| default_port(uri.scheme()));
         self.host = host;
-        for i in 0..self.headers.len() {
-            if self.headers[i].key == "Host" {
-                self.headers[i].value = host.as_bytes();
+        match self.path.form() {
+            UriForm::Absolute => self.path = uri,
+            UriForm::Authority => {
+                self.path.set_host(&self.host);
+                self.path.set_port(Some(self.port));
             }
+            UriForm::Origin => {
+                // "http://host" has empty path, but origin-form can't be empty
+                self.path.set_path(match uri.path() {
+                    "" => "/",
+                    path => path,
+                });
+                self.path.set_query(uri.query());
+            }
+            UriForm::Asterisk => {}
         }
+        self.update_host_header();
+        Ok(())
     }
-    pub fn parse(buf: &'a [u8]) -> Result<Request<'a>, String> {
+    // rewrite url with regex, "$1" or "${1}" in replacement is the first capture group
+    // return false if url doesn't match
+    pub fn rewrite(&mut self, re: &Regex, replace: &str) -> Result<bool, ParseError> {
+        let url = self.url();
+        if !re.is_match(&url) {
+            return Ok(false);
+        }
+        self.set_url(&re.replace(&url, replace))?;
+        Ok(true)
+    }
+    // proxy only speaks plain HTTP to server
+    // "https://host/" would be sent to port 443 in plain text
+    pub fn check_scheme(&self) -> Result<(), ParseError> {
+        if is_http(self.path.scheme()) {
+            Ok(())
+        } else {
+            Err(ParseError::Scheme)
+        }
+    }
+    pub fn parse(buf: &'a [u8]) -> Result<Request<'a>, ParseError> {
         // first, find position of body
         let (body_pos, body) = match find(buf, b"\r\n\r\n") {
             Some(pos) => (pos, &buf[pos + 4..]),
@@ -73,103 +591,718 @@ impl<'a> Request<'a> {
         // split it by Line break "\r\n" and return a iterator
         let mut iter = split(header, b"\r\n");
         // find first line and covert it from &[u8] to str
-        let first_line = iter.next().ok_or("http massage change line with \\r\\n")?;
-        let first_line = std::str::from_utf8(first_line)
-            .map_err(|_| "Http message first line contain invalid utf-8")?;
+        let first_line = iter.next().ok_or(ParseError::Empty)?;
+        let first_line = std::str::from_utf8(first_line).map_err(|_| ParseError::StartLine)?;
         // split first line by space
-        let mut first_line_iter = first_line.split(" ");
+        let mut first_line_iter = first_line.split(' ');
         // find method path and version
-        let method = first_line_iter
-            .next()
-            .ok_or("http massage start with method")?;
-        let path = first_line_iter
-            .next()
-            .ok_or("http massage have path")?
-            .to_string();
-        let version = first_line_iter.next().ok_or("http massage have version")?;
+        let method = first_line_iter.next().ok_or(ParseError::Method)?;
+        // method is case-sensitive token, we don't need to know what it means
+        if !is_token(method) {
+            return Err(ParseError::Method);
+        }
+        let path = first_line_iter.next().ok_or(ParseError::Path)?;
+        let path = Uri::parse(path, method == "CONNECT")?;
+        let version = first_line_iter.next().ok_or(ParseError::Version)?;
         // find Host in headers
         let mut host = None;
-        let headers = iter
-            //map header string to struct Header
-            .map(|x| {
-                let colon_pos = find(x, b":").expect("http header use : split k&v");
-                let key = std::str::from_utf8(&x[..colon_pos])
-                    .expect("Header key contain invalid utf-8")
-                    .trim();
-                let colon = std::str::from_utf8(&x[colon_pos..colon_pos + 1])
-                    .expect("Header colon contain invalid utf-8");
-
-                // find Host in headers
-                if key == "Host" {
-                    host = Some(
-                        std::str::from_utf8(&x[colon_pos + 1..])
-                            .expect("Host contain invalid utf-8")
-                            .trim(),
-                    )
-                }
-
-                // prevent upgrade HTTP to HTTPS
-                if key == "Upgrade-Insecure-Requests" {
-                    return None;
+        let mut headers = Headers::new();
+        // map header string to struct Header
+        for x in iter {
+            let colon_pos = find(x, b":").ok_or(ParseError::HeaderColon)?;
+            let key = std::str::from_utf8(&x[..colon_pos])
+                .map_err(|_| ParseError::HeaderName)?
+                .trim();
+
+            // find Host in headers, "host: " is the same as "Host: "
+            // two of them may be two different hosts, see RFC 9112 section 3.2
+            if key.eq_ignore_ascii_case("Host") {
+                if host.is_some() {
+                    return Err(ParseError::ManyHost);
                 }
+                host = Some(
+                    std::str::from_utf8(&x[colon_pos + 1..])
+                        .map_err(|_| ParseError::Host)?
+                        .trim(),
+                )
+            }
 
-                Some(Header {
-                    key,
-                    colon,
-                    value: trim(&x[colon_pos + 1..]),
-                })
-            })
-            // filter that None
-            .filter(|x| x.is_some())
-            // unwrap some in option
-            .map(|x| x.expect("impossible"))
-            // collect to Vec<Header>
-            .collect();
+            headers.append(key, trim(&x[colon_pos + 1..]));
+        }
         // return Err when don't find host
-        let host = host.ok_or("dont know host")?;
-        Ok(Request {
+        let host = host.ok_or(ParseError::NoHost)?;
+        // proxy must use host in absolute url and ignore "Host" header
+        // see RFC 7230 section 5.4
+        let (host, port) = match path.host() {
+            Some(host) => (host, path.port()),
+            None => split_authority(host).ok_or(ParseError::Host)?,
+        };
+        let host = host.to_owned();
+        let port = port.unwrap_or_else(|| match method {
+            "CONNECT" => 443,
+            _ => default_port(path.scheme()),
+        });
+        let mut req = Request {
             method,
             path,
             version,
             headers,
             host,
+            port,
             body,
-        })
+        };
+        // server gets the host in absolute url too, not the one client put in "Host"
+        if req.path.form() == UriForm::Absolute {
+            req.update_host_header();
+        }
+        // buffer may contain the next request after this one
+        // only Content-Length bytes belong to this request
+        // chunked body is kept as it is, since we don't know its length before decoding
+        match req.body_length() {
+            Ok(BodyLength::Length(len)) if len < req.body.len() as u64 => {
+                req.body = &req.body[..len as usize]
+            }
+            Ok(BodyLength::Empty) => req.body = &req.body[..0],
+            _ => {}
+        }
+        Ok(req)
     }
     // write pain text HTTP request
     pub fn write<T>(&self, f: &mut T) -> std::io::Result<()>
     where
         T: std::io::Write,
     {
-        write!(f, "{} {} {}\r\n", self.method, self.path, self.version)?;
-        for header in &self.headers {
-            write!(f, "{}{} ", header.key, header.colon)?;
-            f.write(header.value)?;
-            f.write(b"\r\n")?;
+        // every write() to TcpStream may be a packet, so format it first
+        let mut buf = Vec::new();
+        write!(buf, "{} {} {}\r\n", self.method, self.path, self.version)?;
+        self.headers.write(&mut buf)?;
+        buf.extend_from_slice(b"\r\n");
+        // body is sent as it is, don't add anything after it
+        // or server will take it as the start of next request
+        buf.extend_from_slice(self.body);
+        f.write_all(&buf)
+    }
+    // request only has body when Content-Length or Transfer-Encoding is present
+    pub fn body_length(&self) -> Result<BodyLength, ParseError> {
+        let headers = &self.headers;
+        // server may trust the other one, see RFC 9112 section 6.3
+        if headers.contains("Transfer-Encoding") && headers.contains("Content-Length") {
+            return Err(ParseError::TransferEncoding);
         }
-        f.write(b"\r\n")?;
-        if self.body.len() > 0 {
-            f.write(self.body)?;
-            f.write(b"\r\n")?;
+        let transfer_encoding = headers.get_all("Transfer-Encoding");
+        match message_length(transfer_encoding, headers.get_all("Content-Length"))? {
+            // we can't know where the body ends, see RFC 7230 section 3.3.3
+            Some(BodyLength::Close) => Err(ParseError::TransferEncoding),
+            Some(length) => Ok(length),
+            None => Ok(BodyLength::Empty),
         }
-        Ok(())
+    }
+    // whether client want to send next request on this connection
+    pub fn keep_alive(&self) -> bool {
+        // browser send "Proxy-Connection" instead of "Connection" to proxy
+        let connection = self
+            .headers
+            .get("Connection")
+            .or_else(|| self.headers.get("Proxy-Connection"));
+        keep_alive(self.version, connection)
+    }
+    // client waits for "100 Continue" before sending body, see RFC 9110 section 10.1.1
+    // HTTP/1.0 client doesn't know interim response, so it is ignored
+    pub fn expect_continue(&self) -> bool {
+        self.version != "HTTP/1.0"
+            && self
+                .headers
+                .get("Expect")
+                .is_some_and(|value| trim_both(value).eq_ignore_ascii_case(b"100-continue"))
+    }
+    // whether client already has the response with these validators
+    // If-Modified-Since is ignored if If-None-Match is given, see RFC 9110 section 13.2.2
+    pub fn not_modified(&self, etag: Option<&[u8]>, last_modified: Option<&[u8]>) -> bool {
+        if self.headers.contains("If-None-Match") {
+            // weak comparison, W/"1" is the same as "1"
+            let weak = |tag: &'_ [u8]| -> Vec<u8> {
+                let tag = trim_both(tag);
+                tag.strip_prefix(b"W/").unwrap_or(tag).to_vec()
+            };
+            // "*" matches any response
+            let etag = etag.map(weak);
+            return self
+                .headers
+                .get_all("If-None-Match")
+                .flat_map(|value| split(value, b","))
+                .any(|tag| trim_both(tag) == b"*" || Some(weak(tag)) == etag);
+        }
+        let since = self.headers.get("If-Modified-Since").and_then(parse_date);
+        match (since, last_modified.and_then(parse_date)) {
+            (Some(since), Some(modified)) => modified <= since,
+            _ => false,
+        }
+    }
+
+    // absolute url of this request, "http://host:port/path?query"
+    // port is omitted if it is the default one
+    // CONNECT has no path, it looks like "https://host:port/"
+    pub fn url(&self) -> String {
+        let scheme = match self.path.scheme() {
+            _ if self.method == "CONNECT" => "https".to_owned(),
+            Some(scheme) => scheme.to_ascii_lowercase(),
+            None => "http".to_owned(),
+        };
+        let mut url = format!("{}://{}", scheme, self.host);
+        if self.port != default_port(Some(&scheme)) {
+            url.push_str(&format!(":{}", self.port));
+        }
+        match self.path.form() {
+            UriForm::Origin | UriForm::Absolute if !self.path.path().is_empty() => {
+                url.push_str(self.path.path())
+            }
+            _ => url.push('/'),
+        }
+        if let Some(query) = self.path.query() {
+            url.push('?');
+            url.push_str(query);
+        }
+        url
     }
 }
-// display HTTP request message
-impl<'a> fmt::Display for Request<'a> {
+
+pub struct Response<'a> {
+    // "HTTP/1.1"
+    pub version: &'a str,
+
+    // "200" of "HTTP/1.1 200 OK"
+    pub code: u16,
+
+    // "OK" of "HTTP/1.1 200 OK", may be empty
+    pub reason: &'a str,
+
+    pub headers: Headers<'a>,
+
+    // may not be valid UTF-8
+    pub body: &'a [u8],
+}
+
+impl<'a> Response<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Response<'a>, ParseError> {
+        // first, find position of body
+        let (body_pos, body) = match find(buf, b"\r\n\r\n") {
+            Some(pos) => (pos, &buf[pos + 4..]),
+            None => (buf.len(), &buf[buf.len()..]),
+        };
+        let mut iter = split(&buf[..body_pos], b"\r\n");
+        // "HTTP/1.1 200 OK"
+        let status_line = iter.next().ok_or(ParseError::Empty)?;
+        let status_line = std::str::from_utf8(status_line).map_err(|_| ParseError::StartLine)?;
+        // reason phrase may contain space
+        let mut status_iter = status_line.splitn(3, ' ');
+        let version = status_iter.next().ok_or(ParseError::Version)?;
+        let code = status_iter
+            .next()
+            .filter(|code| code.len() == 3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or(ParseError::Status)?;
+        let reason = status_iter.next().unwrap_or("");
+        let mut headers = Headers::new();
+        for x in iter {
+            let colon_pos = find(x, b":").ok_or(ParseError::HeaderColon)?;
+            let key = std::str::from_utf8(&x[..colon_pos])
+                .map_err(|_| ParseError::HeaderName)?
+                .trim();
+            headers.append(key, trim(&x[colon_pos + 1..]));
+        }
+        let mut res = Response {
+            version,
+            code,
+            reason,
+            headers,
+            body,
+        };
+        // only Content-Length bytes belong to this response
+        if let Ok(BodyLength::Length(len)) = res.body_length("GET") {
+            if len < res.body.len() as u64 {
+                res.body = &res.body[..len as usize];
+            }
+        }
+        Ok(res)
+    }
+    // write pain text HTTP response
+    pub fn write<T>(&self, f: &mut T) -> std::io::Result<()>
+    where
+        T: std::io::Write,
+    {
+        // the same as Request::write(), one write() for the whole message
+        let mut buf = Vec::new();
+        write!(buf, "{} {} {}\r\n", self.version, self.code, self.reason)?;
+        self.headers.write(&mut buf)?;
+        buf.extend_from_slice(b"\r\n");
+        buf.extend_from_slice(self.body);
+        f.write_all(&buf)
+    }
+    // method of request is needed, response of HEAD has no body
+    // see RFC 7230 section 3.3.3
+    pub fn body_length(&self, method: &str) -> Result<BodyLength, ParseError> {
+        if method == "HEAD" || self.code / 100 == 1 || self.code == 204 || self.code == 304 {
+            return Ok(BodyLength::Empty);
+        }
+        // response without length ends when server close connection
+        Ok(message_length(
+            self.headers.get_all("Transfer-Encoding"),
+            self.headers.get_all("Content-Length"),
+        )?
+        .unwrap_or(BodyLength::Close))
+    }
+    // whether server keep connection open after this response
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.version, self.headers.get("Connection"))
+    }
+    // "timeout" or "max" in "Keep-Alive: timeout=5, max=100"
+    pub fn keep_alive_param(&self, name: &str) -> Option<u64> {
+        keep_alive_param(self.headers.get("Keep-Alive")?, name)
+    }
+}
+
+// display HTTP response message
+impl<'a> fmt::Display for Response<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "START HTTP REQUEST\n")?;
-        write!(f, "{} {} {}\r\n", self.method, self.path, self.version)?;
-        for header in &self.headers {
-            write!(f, "{}{} ", header.key, header.colon)?;
-            if let Ok(value_str) = std::str::from_utf8(header.value) {
-                write!(f, "{}\r\n", value_str)?;
+        writeln!(f, "START HTTP RESPONSE")?;
+        write!(f, "{} {} {}\r\n", self.version, self.code, self.reason)?;
+        write!(f, "{}\r\n", self.headers)?;
+        if !self.body.is_empty() {
+            if let Ok(body_str) = std::str::from_utf8(self.body) {
+                write!(f, "{}", body_str)?;
             } else {
-                write!(f, "[invalid utf8 key]\r\n")?;
+                write!(f, "[invalid utf8 body]")?;
             }
         }
-        write!(f, "\r\n")?;
-        if self.body.len() > 0 {
+        write!(f, "END HTTP RESPONSE")
+    }
+}
+
+// Why a HTTP message can't be parsed
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    // message is empty
+    Empty,
+    // request line or status line is not valid UTF-8
+    StartLine,
+    // method is missing or is not a token
+    Method,
+    // request line don't have path
+    Path,
+    // request line or status line don't have version
+    Version,
+    // status code is not 3 digits
+    Status,
+    // header line don't use ':' to split name and value
+    HeaderColon,
+    // header name is not valid UTF-8
+    HeaderName,
+    // value of Host is not valid UTF-8, or port in it is not a number
+    Host,
+    // request don't have Host header
+    NoHost,
+    // request has more than one Host header
+    ManyHost,
+    // port in request target is not a number
+    Uri,
+    // scheme of absolute url is not http
+    Scheme,
+    // Content-Length is not a number
+    ContentLength,
+    // request body is not chunked, we don't know where it ends
+    TransferEncoding,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ParseError::Empty => "http massage change line with \\r\\n",
+            ParseError::StartLine => "Http message first line contain invalid utf-8",
+            ParseError::Method => "http massage start with method",
+            ParseError::Path => "http massage have path",
+            ParseError::Version => "http massage have version",
+            ParseError::Status => "http response have 3 digits status code",
+            ParseError::HeaderColon => "http header use : split k&v",
+            ParseError::HeaderName => "Header key contain invalid utf-8",
+            ParseError::Host => "Host contain invalid utf-8",
+            ParseError::NoHost => "dont know host",
+            ParseError::ManyHost => "more than one Host header",
+            ParseError::Uri => "Invalid request target",
+            ParseError::Scheme => "only http:// url is supported",
+            ParseError::ContentLength => "Invalid Content-Length",
+            ParseError::TransferEncoding => "invalid Transfer-Encoding",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// How to find the end of message body
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BodyLength {
+    // no body at all
+    Empty,
+    // "Content-Length: 1234"
+    Length(u64),
+    // "Transfer-Encoding: chunked"
+    Chunked,
+    // body ends when server close connection
+    Close,
+}
+
+// None if message has neither Transfer-Encoding nor Content-Length
+// `transfer_encoding` and `content_length` are values of all headers of the name
+fn message_length<'b>(
+    transfer_encoding: impl Iterator<Item = &'b [u8]>,
+    content_length: impl Iterator<Item = &'b [u8]>,
+) -> Result<Option<BodyLength>, ParseError> {
+    // "Transfer-Encoding: gzip" and "Transfer-Encoding: chunked" is "gzip, chunked"
+    let codings: Vec<&[u8]> = transfer_encoding
+        .flat_map(|value| split(value, b","))
+        .map(trim_both)
+        .collect();
+    // Transfer-Encoding overrides Content-Length
+    if !codings.is_empty() {
+        let chunked = |coding: &[u8]| coding.eq_ignore_ascii_case(b"chunked");
+        let count = codings.iter().filter(|coding| chunked(coding)).count();
+        // chunked must be the last one and only once, or the body can be read in two ways
+        return match count {
+            _ if codings.iter().any(|coding| coding.is_empty()) => {
+                Err(ParseError::TransferEncoding)
+            }
+            0 => Ok(Some(BodyLength::Close)),
+            1 if codings.last().is_some_and(|last| chunked(last)) => Ok(Some(BodyLength::Chunked)),
+            _ => Err(ParseError::TransferEncoding),
+        };
+    }
+    // "Content-Length: 5, 5" and repeated headers are fine if they are the same,
+    // different lengths can't be trusted, see RFC 9112 section 6.3
+    let mut length = None;
+    for cl in content_length.flat_map(|value| split(value, b",")) {
+        let cl = std::str::from_utf8(trim_both(cl))
+            .ok()
+            .and_then(|cl| cl.parse::<u64>().ok())
+            .ok_or(ParseError::ContentLength)?;
+        if length.is_some_and(|length| length != cl) {
+            return Err(ParseError::ContentLength);
+        }
+        length = Some(cl);
+    }
+    Ok(length.map(BodyLength::Length))
+}
+
+// HTTP/1.1 keep connection open by default, HTTP/1.0 close it by default
+fn keep_alive(version: &str, connection: Option<&[u8]>) -> bool {
+    let has_option = |option: &[u8]| {
+        connection
+            .map(|value| split(value, b",").any(|x| trim_both(x).eq_ignore_ascii_case(option)))
+            .unwrap_or(false)
+    };
+    if version == "HTTP/1.0" {
+        has_option(b"keep-alive")
+    } else {
+        !has_option(b"close")
+    }
+}
+
+// find "max" in "timeout=5, max=100"
+fn keep_alive_param(params: &[u8], name: &str) -> Option<u64> {
+    split(params, b",")
+        .map(trim_both)
+        .filter_map(|param| {
+            let eq = find(param, b"=")?;
+            if param[..eq].eq_ignore_ascii_case(name.as_bytes()) {
+                std::str::from_utf8(&param[eq + 1..]).ok()?.parse().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+// read header of HTTP message line by line, include the blank line
+// return 0 if connection is closed before we read anything
+pub fn read_head<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+    buf.clear();
+    loop {
+        let start = buf.len();
+        // a line without end must not take all memory, read one byte more than allowed
+        let limit = (MAX_HEAD_LEN + 1 - buf.len()) as u64;
+        if reader.by_ref().take(limit).read_until(b'\n', buf)? == 0 {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed in header",
+            ));
+        }
+        if &buf[start..] == b"\r\n" {
+            // client may send empty line before request line
+            if start == 0 {
+                buf.clear();
+                continue;
+            }
+            return Ok(buf.len());
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "header too long",
+            ));
+        }
+    }
+}
+
+// copy message body from reader to writer, stop at the end of body
+// so that next message on the same connection is kept in reader
+#[cfg(test)]
+pub fn copy_body<R, W>(reader: &mut R, writer: &mut W, length: BodyLength) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write,
+{
+    copy_body_inspect(reader, writer, length, |_| {})
+}
+
+// copy_body() that show every piece of body to `inspect` after it is written
+// next message on the same connection is kept in reader
+// chunked body is shown decoded, without chunk size and trailers
+pub fn copy_body_inspect<R, W, F>(
+    reader: &mut R,
+    writer: &mut W,
+    length: BodyLength,
+    inspect: F,
+) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&[u8]),
+{
+    match length {
+        BodyLength::Empty => Ok(0),
+        BodyLength::Length(len) => copy_exact(reader, &mut Inspect { writer, inspect }, len),
+        BodyLength::Chunked => copy_chunked(reader, writer, inspect),
+        BodyLength::Close => io::copy(reader, &mut Inspect { writer, inspect }),
+    }
+}
+
+// writer that show written bytes to a closure
+struct Inspect<W, F> {
+    writer: W,
+    inspect: F,
+}
+
+impl<W: Write, F: FnMut(&[u8])> Write for Inspect<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        (self.inspect)(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn copy_exact<R, W>(reader: &mut R, writer: &mut W, len: u64) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write,
+{
+    let copied = io::copy(&mut reader.take(len), writer)?;
+    if copied < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed in body",
+        ));
+    }
+    Ok(copied)
+}
+
+// copy chunked body without chunk size and trailers, for client that can't read chunked
+// body ends when connection is closed
+pub fn copy_chunked_decoded<R, W, F>(reader: &mut R, writer: &mut W, inspect: F) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&[u8]),
+{
+    io::copy(
+        &mut ChunkedReader::new(reader),
+        &mut Inspect { writer, inspect },
+    )
+}
+
+// chunked body looks like
+// 5\r\n
+// hello\r\n
+// 0\r\n
+// Trailer: value\r\n
+// \r\n
+// body is decoded and encoded again chunk by chunk, it is never buffered as a whole
+fn copy_chunked<R, W, F>(reader: &mut R, writer: &mut W, inspect: F) -> io::Result<u64>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&[u8]),
+{
+    let mut decoder = ChunkedReader::new(reader);
+    let mut encoder = ChunkedWriter::new(writer);
+    let total = io::copy(
+        &mut decoder,
+        &mut Inspect {
+            writer: &mut encoder,
+            inspect,
+        },
+    )?;
+    encoder.finish(&decoder.trailers)?;
+    Ok(total)
+}
+
+// decode chunked body, read() return data in chunks without size and CRLF
+pub struct ChunkedReader<R> {
+    reader: R,
+    // bytes left in current chunk
+    remain: u64,
+    // last chunk "0\r\n" is read
+    done: bool,
+    // trailer lines after last chunk, each end with "\r\n"
+    pub trailers: Vec<u8>,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            remain: 0,
+            done: false,
+            trailers: Vec::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let mut line = Vec::new();
+        if self.remain == 0 {
+            read_line(&mut self.reader, &mut line)?;
+            self.remain = chunk_size(&line)?;
+            if self.remain == 0 {
+                // trailers end with a blank line
+                loop {
+                    read_line(&mut self.reader, &mut line)?;
+                    if line == b"\r\n" {
+                        self.done = true;
+                        return Ok(0);
+                    }
+                    self.trailers.extend_from_slice(&line);
+                    if self.trailers.len() > MAX_HEAD_LEN {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "trailers too long",
+                        ));
+                    }
+                }
+            }
+        }
+        let max = std::cmp::min(buf.len() as u64, self.remain) as usize;
+        let bytes = self.reader.read(&mut buf[..max])?;
+        if bytes == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed in chunked body",
+            ));
+        }
+        self.remain -= bytes as u64;
+        // every chunk data is followed by "\r\n"
+        if self.remain == 0 {
+            read_line(&mut self.reader, &mut line)?;
+            if line != b"\r\n" {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunk data don't end with CRLF",
+                ));
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+// encode body as chunked, every write() is a chunk
+// call finish() to write the last chunk
+pub struct ChunkedWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> ChunkedWriter<W> {
+        ChunkedWriter { writer }
+    }
+
+    // trailers should be header lines end with "\r\n"
+    pub fn finish(mut self, trailers: &[u8]) -> io::Result<()> {
+        self.writer.write_all(b"0\r\n")?;
+        self.writer.write_all(trailers)?;
+        self.writer.write_all(b"\r\n")
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // empty chunk means end of body, so never write it here
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.writer, "{:x}\r\n", buf.len())?;
+        self.writer.write_all(buf)?;
+        self.writer.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// chunk size or trailer line, as long as a header at most
+fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>) -> io::Result<()> {
+    line.clear();
+    let limit = MAX_HEAD_LEN as u64 + 1;
+    if reader.by_ref().take(limit).read_until(b'\n', line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed in chunked body",
+        ));
+    }
+    if line.len() > MAX_HEAD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "line of chunked body too long",
+        ));
+    }
+    Ok(())
+}
+
+// "1a;name=value\r\n" -> 26
+fn chunk_size(line: &[u8]) -> io::Result<u64> {
+    let size = split(line, b";").next().map(trim_both).unwrap_or(b"");
+    std::str::from_utf8(size)
+        .ok()
+        .and_then(|size| u64::from_str_radix(size, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))
+}
+// display HTTP request message
+impl<'a> fmt::Display for Request<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "START HTTP REQUEST")?;
+        write!(f, "{} {} {}\r\n", self.method, self.path, self.version)?;
+        write!(f, "{}\r\n", self.headers)?;
+        if !self.body.is_empty() {
             if let Ok(body_str) = std::str::from_utf8(self.body) {
                 write!(f, "{}", body_str)?;
             } else {
@@ -180,6 +1313,111 @@ impl<'a> fmt::Display for Request<'a> {
     }
 }
 
+// reason phrase of status code, used when proxy create a response
+pub fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// seconds since epoch of HTTP-date, see RFC 9110 section 5.6.7
+// "Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT" or "Sun Nov  6 08:49:37 1994"
+pub fn parse_date(value: &[u8]) -> Option<u64> {
+    let value = std::str::from_utf8(value).ok()?;
+    let parts: Vec<&str> = value
+        .split([' ', '-', ','])
+        .filter(|x| !x.is_empty())
+        .collect();
+    let (day, month, year, time) = match parts[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        [_, month, day, time, year] => (day, month, year, time),
+        _ => return None,
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|x| *x == month)? as u64 + 1;
+    let year: u64 = match year.parse().ok()? {
+        year @ 0..=69 => year + 2000,
+        year @ 70..=99 => year + 1900,
+        year => year,
+    };
+    let time: Vec<u64> = time
+        .split(':')
+        .map(|x| x.parse().ok())
+        .collect::<Option<_>>()?;
+    let valid = year >= 1970 && (1..=31).contains(&day);
+    match time[..] {
+        [hour, minute, second] if valid && hour < 24 && minute < 60 && second < 61 => {
+            Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+        }
+        _ => None,
+    }
+}
+
+// days since 1970-01-01, year must not be before 1970
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // year starts from March, so leap day is the last day
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// origin-form and authority-form have no scheme
+fn is_http(scheme: Option<&str>) -> bool {
+    scheme.is_none_or(|scheme| scheme.eq_ignore_ascii_case("http"))
+}
+
+// port used when url don't have one
+pub fn default_port(scheme: Option<&str>) -> u16 {
+    match scheme {
+        Some(scheme) if scheme.eq_ignore_ascii_case("https") => 443,
+        _ => 80,
+    }
+}
+
+// token = 1*tchar
+// tchar = "!" / "#" / "$" / "%" / "&" / "'" / "*" / "+" / "-" / "." /
+//         "^" / "_" / "`" / "|" / "~" / DIGIT / ALPHA
+// see RFC 7230 section 3.2.6
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
 // some tools for [u8]
 
 // split for [u8]
@@ -192,7 +1430,12 @@ struct U8SplitIter<'a> {
 impl<'a> Iterator for U8SplitIter<'a> {
     type Item = &'a [u8];
     fn next(&mut self) -> Option<Self::Item> {
-        let next_pos = find(&self.buf[self.pos..], self.pat);
+        // empty pattern would never move forward, return the rest at once
+        let next_pos = if self.pat.is_empty() {
+            None
+        } else {
+            find(&self.buf[self.pos..], self.pat)
+        };
         match next_pos {
             Some(next_pos) => {
                 let last_pos = self.pos;
@@ -213,19 +1456,12 @@ impl<'a> Iterator for U8SplitIter<'a> {
 }
 
 fn find(buf: &[u8], pat: &[u8]) -> Option<usize> {
-    assert!(pat.len() > 0);
-    for i in 0..buf.len() {
-        for j in 0..pat.len() {
-            if buf[i + j] != pat[j] {
-                break;
-            } else {
-                if j == pat.len() - 1 {
-                    return Some(i);
-                }
-            }
-        }
+    // empty pattern is found everywhere, windows(0) would panic
+    if pat.is_empty() {
+        return Some(0);
     }
-    None
+    // windows() never run out of buf, even if buf is shorter than pat
+    buf.windows(pat.len()).position(|window| window == pat)
 }
 
 fn split<'a>(buf: &'a [u8], pat: &'static [u8]) -> U8SplitIter<'a> {
@@ -233,15 +1469,28 @@ fn split<'a>(buf: &'a [u8], pat: &'static [u8]) -> U8SplitIter<'a> {
 }
 
 // trim space in [u8]
-fn trim<'a>(buf: &'a [u8]) -> &'a [u8] {
+fn trim(buf: &[u8]) -> &[u8] {
     for i in 0..buf.len() {
-        if buf[i] != ' ' as u8 {
+        if buf[i] != b' ' {
             return &buf[i..];
         }
     }
     &buf[buf.len()..]
 }
 
+// trim whitespace at both side, include "\r\n"
+fn trim_both(buf: &[u8]) -> &[u8] {
+    let start = buf
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(buf.len());
+    let end = buf
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(start, |pos| pos + 1);
+    &buf[start..end]
+}
+
 // ************TEST*************//
 
 // this is a macro to test Request
@@ -3457,7 +4706,8 @@ req! {
         assert_eq!(req.version, "HTTP/1.1");
         assert_eq!(req.headers.len(), 1);
         assert_eq!(req.headers[0].key, "Host");
-        assert_eq!(req.headers[0].value, b"");
+        // host in absolute url replaces the one in header
+        assert_eq!(req.headers[0].value, b"example.com");
     }
 }
 
@@ -3655,3 +4905,1168 @@ req! {
         assert_eq!(req.headers[0].value, b"example.org");
     }
 }
+
+// every method is a token, proxy forward it without knowing what it means
+
+req! {
+    method_put,
+    "PUT http://example.org/file.txt HTTP/1.1\r\nHost: example.org\r\nContent-Length: 5\r\n\r\nhello",
+    |req| {
+        assert_eq!(req.method, "PUT");
+        assert_eq!(req.path, "http://example.org/file.txt");
+        assert_eq!(req.host, "example.org");
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.body, b"hello");
+    }
+}
+
+req! {
+    method_delete,
+    "DELETE /items/42 HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.method, "DELETE");
+        assert_eq!(req.path, "/items/42");
+        assert_eq!(req.body, b"");
+    }
+}
+
+req! {
+    method_patch,
+    "PATCH /items/42 HTTP/1.1\r\nHost: example.org\r\nContent-Length: 13\r\n\r\n{\"a\":\"b\"}\r\n\r\n",
+    |req| {
+        assert_eq!(req.method, "PATCH");
+        assert_eq!(req.path, "/items/42");
+        assert_eq!(req.body, b"{\"a\":\"b\"}\r\n\r\n");
+    }
+}
+
+req! {
+    method_head,
+    "HEAD / HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.method, "HEAD");
+        assert_eq!(req.path, "/");
+        assert_eq!(req.version, "HTTP/1.1");
+    }
+}
+
+req! {
+    method_options_asterisk,
+    "OPTIONS * HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.method, "OPTIONS");
+        assert_eq!(req.path, "*");
+        assert_eq!(req.host, "example.org");
+    }
+}
+
+req! {
+    method_extension,
+    "PROPFIND /dav/ HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.method, "PROPFIND");
+        assert_eq!(req.path, "/dav/");
+    }
+}
+
+#[test]
+fn method_invalid_token() {
+    let parse = |buf: &[u8]| Request::parse(buf).err();
+    assert_eq!(
+        parse(b"GE\"T / HTTP/1.1\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Method)
+    );
+    assert_eq!(
+        parse(b"GET(/) / HTTP/1.1\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Method)
+    );
+    assert_eq!(
+        parse(b" / HTTP/1.1\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Method)
+    );
+}
+
+#[test]
+fn modify_host_keep_asterisk() {
+    let mut req = Request::parse(b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+    req.modify_host("b");
+    assert_eq!(req.path, "*");
+    assert_eq!(req.host, "b");
+    assert_eq!(req.headers[0].value, b"b");
+}
+
+// keep-alive and where the message ends
+
+req! {
+    keep_alive_http11_default,
+    "GET / HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert!(req.keep_alive());
+        assert_eq!(req.body_length(), Ok(BodyLength::Empty));
+    }
+}
+
+req! {
+    keep_alive_connection_close,
+    "GET / HTTP/1.1\r\nHost: example.org\r\nconnection: Close\r\n\r\n",
+    |req| {
+        assert!(!req.keep_alive());
+    }
+}
+
+req! {
+    keep_alive_http10_proxy_connection,
+    "GET / HTTP/1.0\r\nHost: example.org\r\nProxy-Connection: keep-alive\r\n\r\n",
+    |req| {
+        assert!(req.keep_alive());
+    }
+}
+
+req! {
+    body_length_chunked_with_content_length,
+    "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+    |req| {
+        assert_eq!(req.body_length(), Err(ParseError::TransferEncoding));
+    }
+}
+
+req! {
+    body_length_repeated_transfer_encoding,
+    "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n",
+    |req| {
+        assert_eq!(req.body_length(), Ok(BodyLength::Chunked));
+    }
+}
+
+#[test]
+fn body_length_invalid_transfer_encoding() {
+    let length = |te: &str| {
+        let buf = format!("POST / HTTP/1.1\r\nHost: a\r\n{}\r\n", te);
+        Request::parse(buf.as_bytes()).unwrap().body_length()
+    };
+    let invalid = Err(ParseError::TransferEncoding);
+    assert_eq!(length("Transfer-Encoding: chunked, chunked\r\n"), invalid);
+    assert_eq!(
+        length("Transfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n"),
+        invalid
+    );
+    assert_eq!(length("Transfer-Encoding: chunked, gzip\r\n"), invalid);
+    assert_eq!(length("Transfer-Encoding: ,chunked\r\n"), invalid);
+    assert_eq!(length("Transfer-Encoding: gzip,\r\n"), invalid);
+    // response can end when connection is closed
+    let res = Response::parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n").unwrap();
+    assert_eq!(res.body_length("GET"), Ok(BodyLength::Close));
+    let res =
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n";
+    let res = Response::parse(res).unwrap();
+    assert_eq!(res.body_length("GET"), invalid);
+}
+
+req! {
+    body_length_same_content_length,
+    "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 3\r\ncontent-length: 3\r\n\r\n",
+    |req| {
+        assert_eq!(req.body_length(), Ok(BodyLength::Length(3)));
+    }
+}
+
+req! {
+    body_length_different_content_length,
+    "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nContent-Length: 30\r\n\r\n",
+    |req| {
+        assert_eq!(req.body_length(), Err(ParseError::ContentLength));
+    }
+}
+
+req! {
+    remove_content_length_with_transfer_encoding,
+    "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+    |req| {
+        let mut req = req;
+        req.headers.remove_ignored_length();
+        assert_eq!(req.headers.to_string(), "Host: a\r\nTransfer-Encoding: chunked\r\n");
+        assert_eq!(req.body_length(), Ok(BodyLength::Chunked));
+    }
+}
+
+req! {
+    body_length_invalid_content_length,
+    "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3a\r\n\r\n",
+    |req| {
+        assert!(req.body_length().is_err());
+    }
+}
+
+#[test]
+fn read_head_keep_next_message() {
+    let mut reader: &[u8] = b"\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\nGET /next HTTP/1.1\r\n";
+    let mut buf = Vec::new();
+    assert_eq!(read_head(&mut reader, &mut buf).unwrap(), 27);
+    assert_eq!(buf, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+    assert_eq!(reader, b"GET /next HTTP/1.1\r\n");
+}
+
+#[test]
+fn read_head_long_line() {
+    let line = vec![b'a'; MAX_HEAD_LEN * 4];
+    let mut reader = &line[..];
+    let mut buf = Vec::new();
+    assert!(read_head(&mut reader, &mut buf).is_err());
+    assert_eq!(buf.len(), MAX_HEAD_LEN + 1);
+    // chunk size and trailer lines have the same limit
+    let mut chunked = b"1;".to_vec();
+    chunked.extend(vec![b'a'; MAX_HEAD_LEN * 4]);
+    let mut body = Vec::new();
+    let e = ChunkedReader::new(&chunked[..])
+        .read_to_end(&mut body)
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    let mut trailers = b"0\r\n".to_vec();
+    for _ in 0..MAX_HEAD_LEN / 4 {
+        trailers.extend_from_slice(b"X: abc\r\n");
+    }
+    let e = ChunkedReader::new(&trailers[..])
+        .read_to_end(&mut body)
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn copy_chunked_to_http10() {
+    let mut reader: &[u8] = b"5\r\nhello\r\n6\r\n world\r\n0\r\nX: y\r\n\r\nnext";
+    let (mut body, mut seen) = (Vec::new(), Vec::new());
+    let copied = copy_chunked_decoded(&mut reader, &mut body, |data| seen.extend_from_slice(data));
+    assert_eq!(copied.unwrap(), 11);
+    assert_eq!(
+        (&body[..], &seen[..]),
+        (&b"hello world"[..], &b"hello world"[..])
+    );
+    assert_eq!(reader, b"next");
+}
+
+#[test]
+fn copy_body_stop_at_end() {
+    let mut reader: &[u8] = b"5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n";
+    let mut body = Vec::new();
+    assert_eq!(
+        copy_body(&mut reader, &mut body, BodyLength::Chunked).unwrap(),
+        5
+    );
+    assert_eq!(body, b"5\r\nhello\r\n0\r\n\r\n");
+    assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+    let mut reader: &[u8] = b"hello world";
+    let mut body = Vec::new();
+    assert_eq!(
+        copy_body(&mut reader, &mut body, BodyLength::Length(5)).unwrap(),
+        5
+    );
+    assert_eq!(reader, b" world");
+    assert!(copy_body(&mut reader, &mut body, BodyLength::Length(10)).is_err());
+}
+
+#[test]
+fn copy_body_inspect_decoded() {
+    let mut reader: &[u8] = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+    let (mut body, mut seen) = (Vec::new(), Vec::new());
+    let copied = copy_body_inspect(&mut reader, &mut body, BodyLength::Chunked, |data| {
+        seen.extend_from_slice(data)
+    });
+    assert_eq!(copied.unwrap(), 11);
+    assert_eq!(body, b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+    assert_eq!(seen, b"hello world");
+    let mut reader: &[u8] = b"hello world";
+    let (mut body, mut seen) = (Vec::new(), Vec::new());
+    let copied = copy_body_inspect(&mut reader, &mut body, BodyLength::Length(5), |data| {
+        seen.extend_from_slice(data)
+    });
+    assert_eq!(copied.unwrap(), 5);
+    assert_eq!((&body[..], &seen[..]), (&b"hello"[..], &b"hello"[..]));
+}
+
+// body framing
+
+req! {
+    body_stop_at_content_length,
+    "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n",
+    |req| {
+        assert_eq!(req.body, b"hello");
+    }
+}
+
+req! {
+    body_empty_without_length,
+    "GET / HTTP/1.1\r\nHost: a\r\n\r\nGET /next HTTP/1.1\r\n",
+    |req| {
+        assert_eq!(req.body, b"");
+    }
+}
+
+#[test]
+fn write_body_without_trailing_crlf() {
+    let req = Request::parse(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhi").unwrap();
+    let mut buf = Vec::new();
+    req.write(&mut buf).unwrap();
+    assert_eq!(
+        buf,
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhi"
+    );
+}
+
+#[test]
+fn chunked_decode_with_extension_and_trailers() {
+    let mut reader: &[u8] =
+        b"6;name=value\r\nhello \r\n5\r\nworld\r\n0\r\nExpires: never\r\nX-Sum: 1\r\n\r\nnext";
+    let mut decoder = ChunkedReader::new(&mut reader);
+    let mut body = Vec::new();
+    decoder.read_to_end(&mut body).unwrap();
+    assert_eq!(body, b"hello world");
+    assert_eq!(decoder.trailers, b"Expires: never\r\nX-Sum: 1\r\n");
+    assert_eq!(reader, b"next");
+}
+
+#[test]
+fn chunked_reencode() {
+    let mut reader: &[u8] = b"A;ext\r\n0123456789\r\n0\r\nX-Sum: 1\r\n\r\n";
+    let mut body = Vec::new();
+    assert_eq!(
+        copy_body(&mut reader, &mut body, BodyLength::Chunked).unwrap(),
+        10
+    );
+    assert_eq!(body, b"a\r\n0123456789\r\n0\r\nX-Sum: 1\r\n\r\n");
+}
+
+#[test]
+fn chunked_invalid() {
+    let mut body = Vec::new();
+    let mut reader: &[u8] = b"zz\r\nhello\r\n0\r\n\r\n";
+    assert!(copy_body(&mut reader, &mut body, BodyLength::Chunked).is_err());
+    let mut reader: &[u8] = b"5\r\nhelloX0\r\n\r\n";
+    assert!(copy_body(&mut reader, &mut body, BodyLength::Chunked).is_err());
+    let mut reader: &[u8] = b"5\r\nhel";
+    assert!(copy_body(&mut reader, &mut body, BodyLength::Chunked).is_err());
+}
+
+// ************RESPONSE TEST*************//
+
+// this is a macro to test Response
+macro_rules! res {
+    ($name:ident, $buf:expr, |$arg:ident| $body:expr) => {
+        #[test]
+        fn $name() {
+            let res = Response::parse($buf.as_ref()).unwrap();
+            fn assert_closure($arg: Response) {
+                $body
+            }
+            assert_closure(res);
+        }
+    };
+}
+
+res! {
+    response_ok,
+    "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\nhello",
+    |res| {
+        assert_eq!(res.version, "HTTP/1.1");
+        assert_eq!(res.code, 200);
+        assert_eq!(res.reason, "OK");
+        assert_eq!(res.headers.len(), 2);
+        assert_eq!(res.headers[1].key, "Content-Type");
+        assert_eq!(res.headers[1].value, b"text/plain");
+        assert_eq!(res.body, b"hello");
+        assert_eq!(res.body_length("GET"), Ok(BodyLength::Length(5)));
+        assert!(res.keep_alive());
+    }
+}
+
+res! {
+    response_reason_with_space,
+    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+    |res| {
+        assert_eq!(res.code, 404);
+        assert_eq!(res.reason, "Not Found");
+        assert_eq!(res.body, b"");
+    }
+}
+
+res! {
+    response_empty_reason,
+    "HTTP/1.1 204\r\n\r\n",
+    |res| {
+        assert_eq!(res.code, 204);
+        assert_eq!(res.reason, "");
+        assert_eq!(res.body_length("GET"), Ok(BodyLength::Empty));
+    }
+}
+
+res! {
+    response_keep_alive_params,
+    "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nkeep-alive: timeout=5, max=99\r\n\r\n",
+    |res| {
+        assert_eq!(res.keep_alive_param("timeout"), Some(5));
+        assert_eq!(res.keep_alive_param("max"), Some(99));
+        assert_eq!(res.keep_alive_param("other"), None);
+    }
+}
+
+res! {
+    response_no_body,
+    "HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n",
+    |res| {
+        assert_eq!(res.body_length("GET"), Ok(BodyLength::Empty));
+    }
+}
+
+res! {
+    response_head_no_body,
+    "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n",
+    |res| {
+        assert_eq!(res.body_length("HEAD"), Ok(BodyLength::Empty));
+    }
+}
+
+res! {
+    response_close_delimited,
+    "HTTP/1.0 200 OK\r\n\r\nbody until close",
+    |res| {
+        assert_eq!(res.body_length("GET"), Ok(BodyLength::Close));
+        assert_eq!(res.body, b"body until close");
+        assert!(!res.keep_alive());
+    }
+}
+
+#[test]
+fn response_invalid_status() {
+    assert!(Response::parse(b"HTTP/1.1 OK\r\n\r\n").is_err());
+    assert!(Response::parse(b"HTTP/1.1 2000 OK\r\n\r\n").is_err());
+    assert!(Response::parse(b"HTTP/1.1 200 OK\r\nno colon\r\n\r\n").is_err());
+}
+
+#[test]
+fn response_write_roundtrip() {
+    let raw =
+        b"HTTP/1.1 301 Moved Permanently\r\nLocation: http://a/\r\nContent-Length: 2\r\n\r\nhi";
+    let res = Response::parse(raw).unwrap();
+    let mut buf = Vec::new();
+    res.write(&mut buf).unwrap();
+    assert_eq!(&buf[..], &raw[..]);
+}
+
+// counts write() calls, every call may be a packet on TcpStream
+#[cfg(test)]
+struct Writes(Vec<Vec<u8>>);
+
+#[cfg(test)]
+impl Write for Writes {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.push(buf.to_vec());
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_message_at_once() {
+    let raw = b"POST / HTTP/1.1\r\nHost: a\r\nX-A: 1\r\nContent-Length: 2\r\n\r\nhi";
+    let mut writes = Writes(Vec::new());
+    Request::parse(raw).unwrap().write(&mut writes).unwrap();
+    assert_eq!(writes.0, vec![raw.to_vec()]);
+    let raw = b"HTTP/1.1 200 OK\r\nX-A: 1\r\nX-B: 2\r\nContent-Length: 0\r\n\r\n";
+    let mut writes = Writes(Vec::new());
+    Response::parse(raw).unwrap().write(&mut writes).unwrap();
+    assert_eq!(writes.0, vec![raw.to_vec()]);
+}
+
+#[test]
+fn error_page_template() {
+    use crate::config::ErrorPage;
+    let page = ErrorPage::default();
+    assert_eq!(
+        page.render(404, reason(404), "a.test is not found"),
+        "<html><head><title>404 Not Found</title></head>\n\
+         <body><h1>404 Not Found</h1><p>a.test is not found</p></body></html>"
+    );
+    let page: ErrorPage =
+        toml::from_str("template = \"{code}|{reason}|{message}|{code}\"").unwrap();
+    assert_eq!(page.render(502, reason(502), "x"), "502|Bad Gateway|x|502");
+    // message may come from client
+    assert_eq!(
+        page.render(400, reason(400), "<script>alert(\"&\")</script>"),
+        "400|Bad Request|&lt;script&gt;alert(&quot;&amp;&quot;)&lt;/script&gt;|400"
+    );
+}
+
+// ************FUZZ TEST*************//
+
+// parse must return Err for any malformed input, never panic
+// a tiny xorshift generator is enough, we don't need a crate for it
+#[cfg(test)]
+struct Fuzz(u64);
+
+#[cfg(test)]
+impl Fuzz {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    // glue pieces of HTTP message together randomly
+    fn message(&mut self) -> Vec<u8> {
+        const PIECES: &[&[u8]] = &[
+            b"GET",
+            b"HEAD",
+            b"CONNECT",
+            b"HTTP/1.1",
+            b"HTTP/1.0",
+            b"200",
+            b"OK",
+            b" ",
+            b"  ",
+            b"/",
+            b"*",
+            b"http://",
+            b"Host",
+            b"Content-Length",
+            b"Transfer-Encoding",
+            b"chunked",
+            b"Connection",
+            b"Keep-Alive",
+            b"close",
+            b"timeout=",
+            b"max=",
+            b":",
+            b": ",
+            b",",
+            b";",
+            b"=",
+            b"\r\n",
+            b"\r\n\r\n",
+            b"\r",
+            b"\n",
+            b"0",
+            b"5",
+            b"ff",
+            b"18446744073709551616",
+            b"\xff",
+            b"\xc3",
+            b"\x00",
+            b"\t",
+            b"-",
+        ];
+        let mut buf = Vec::new();
+        for _ in 0..self.below(40) {
+            buf.extend_from_slice(PIECES[self.below(PIECES.len())]);
+        }
+        buf
+    }
+}
+
+// call everything that looks at untrusted bytes
+#[cfg(test)]
+fn fuzz_one(buf: &[u8]) {
+    if let Ok(req) = Request::parse(buf) {
+        let _ = req.body_length();
+        let _ = req.keep_alive();
+        let _ = req.headers.get_all("Host").count();
+        let mut out = Vec::new();
+        req.write(&mut out).unwrap();
+        let _ = req.to_string();
+    }
+    if let Ok(res) = Response::parse(buf) {
+        let _ = res.body_length("GET");
+        let _ = res.keep_alive();
+        let _ = res.keep_alive_param("max");
+        let mut out = Vec::new();
+        res.write(&mut out).unwrap();
+        let _ = res.to_string();
+    }
+    for pat in [&b""[..], b"\r\n", b"\r\n\r\n", b":"].iter() {
+        let _ = find(buf, pat);
+    }
+    for pat in [&b""[..], b"\r\n", b","].iter() {
+        let _ = split(buf, pat).count();
+    }
+    let _ = trim(buf);
+    let _ = trim_both(buf);
+    let mut reader = buf;
+    let _ = read_head(&mut reader, &mut Vec::new());
+    let mut reader = buf;
+    let _ = copy_body(&mut reader, &mut io::sink(), BodyLength::Chunked);
+}
+
+#[test]
+fn fuzz_short_inputs() {
+    // every input up to 4 bytes made of interesting bytes
+    let alphabet = b"G :\r\n\xff0;";
+    let mut inputs: Vec<Vec<u8>> = vec![Vec::new()];
+    for _ in 0..4 {
+        let mut longer = Vec::new();
+        for input in &inputs {
+            for &c in alphabet.iter() {
+                let mut input = input.clone();
+                input.push(c);
+                longer.push(input);
+            }
+        }
+        for input in &longer {
+            fuzz_one(input);
+        }
+        inputs = longer;
+    }
+}
+
+#[test]
+fn fuzz_random_messages() {
+    let mut fuzz = Fuzz(0x2545_f491_4f6c_dd1d);
+    for _ in 0..20000 {
+        fuzz_one(&fuzz.message());
+    }
+}
+
+#[test]
+fn fuzz_mutated_messages() {
+    let mut fuzz = Fuzz(0x9e37_79b9_7f4a_7c15);
+    let valid: &[&[u8]] = &[
+        b"POST http://example.org/a?b HTTP/1.1\r\nHost: example.org\r\nContent-Length: 5\r\nConnection: keep-alive\r\n\r\nhello",
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nKeep-Alive: timeout=5, max=9\r\n\r\n5;a=b\r\nhello\r\n0\r\nX: y\r\n\r\n",
+    ];
+    for message in valid {
+        // cut message at every position
+        for end in 0..message.len() {
+            fuzz_one(&message[..end]);
+        }
+        // replace random bytes
+        for _ in 0..5000 {
+            let mut message = message.to_vec();
+            for _ in 0..1 + fuzz.below(4) {
+                let pos = fuzz.below(message.len());
+                message[pos] = fuzz.next() as u8;
+            }
+            fuzz_one(&message);
+        }
+    }
+}
+
+#[test]
+fn parse_error_kinds() {
+    let parse = |buf: &[u8]| Request::parse(buf).err();
+    assert_eq!(parse(b""), Some(ParseError::Empty));
+    assert_eq!(
+        parse(b"\xff / HTTP/1.1\r\n\r\n"),
+        Some(ParseError::StartLine)
+    );
+    assert_eq!(parse(b"GET\r\nHost: a\r\n\r\n"), Some(ParseError::Path));
+    assert_eq!(
+        parse(b"GET /\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Version)
+    );
+    assert_eq!(
+        parse(b"GET / HTTP/1.1\r\nHost a\r\n\r\n"),
+        Some(ParseError::HeaderColon)
+    );
+    assert_eq!(
+        parse(b"GET / HTTP/1.1\r\n\xff: a\r\n\r\n"),
+        Some(ParseError::HeaderName)
+    );
+    assert_eq!(
+        parse(b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n"),
+        Some(ParseError::Host)
+    );
+    assert_eq!(
+        parse(b"GET / HTTP/1.1\r\nAccept: */*\r\n\r\n"),
+        Some(ParseError::NoHost)
+    );
+    let req = Request::parse(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n");
+    assert_eq!(
+        req.unwrap().body_length(),
+        Err(ParseError::TransferEncoding)
+    );
+}
+
+// ************HEADERS TEST*************//
+
+req! {
+    header_host_lowercase,
+    "GET http://example.org/ HTTP/1.1\r\nhost: example.org\r\nupgrade-insecure-requests: 1\r\n\r\n",
+    |req| {
+        assert_eq!(req.host, "example.org");
+        // it is removed by header policy, not by parser
+        assert_eq!(req.headers.len(), 2);
+        assert_eq!(req.headers.get("HOST"), Some(&b"example.org"[..]));
+        assert_eq!(req.headers.get("Upgrade-Insecure-Requests"), Some(&b"1"[..]));
+    }
+}
+
+#[test]
+fn headers_rename_and_modify() {
+    let mut headers = Headers::new();
+    headers.append("X-Old", "a");
+    headers.append("Accept", "text/html");
+    headers.append("x-old", "b");
+    assert_eq!(headers.rename("X-OLD", "X-New"), 2);
+    assert_eq!(
+        headers.get_all("x-new").collect::<Vec<_>>(),
+        [&b"a"[..], &b"b"[..]]
+    );
+    assert!(!headers.contains("X-Old"));
+    assert_eq!(headers.rename("X-Missing", "X-New"), 0);
+    let changed = headers.modify("x-new", |value| {
+        if value == b"a" {
+            Some(b"c".to_vec())
+        } else {
+            None
+        }
+    });
+    assert_eq!(changed, 1);
+    assert_eq!(
+        headers.to_string(),
+        "X-New: c\r\nAccept: text/html\r\nX-New: b\r\n"
+    );
+}
+
+#[test]
+fn headers_case_insensitive_and_ordered() {
+    let mut headers = Headers::new();
+    headers.append("Set-Cookie", "a=1");
+    headers.append("content-type", "text/html");
+    headers.append("set-cookie", "b=2");
+    assert_eq!(headers.get("Content-Type"), Some(&b"text/html"[..]));
+    assert_eq!(
+        headers.get_all("SET-COOKIE").collect::<Vec<_>>(),
+        vec![&b"a=1"[..], b"b=2"]
+    );
+    assert!(headers.contains("Set-Cookie"));
+    assert_eq!(headers.get("Missing"), None);
+    // original case and order are kept
+    let mut buf = Vec::new();
+    headers.write(&mut buf).unwrap();
+    assert_eq!(
+        buf,
+        b"Set-Cookie: a=1\r\ncontent-type: text/html\r\nset-cookie: b=2\r\n"
+    );
+}
+
+#[test]
+fn headers_insert_replace_first() {
+    let mut headers = Headers::new();
+    headers.append("Accept", "*/*");
+    headers.append("Via", "1.0 a");
+    headers.append("Host", "a");
+    headers.append("via", "1.0 b");
+    headers.insert("VIA", String::from("1.1 proxy"));
+    assert_eq!(headers.len(), 3);
+    assert_eq!(headers[1].key, "Via");
+    assert_eq!(headers[1].value, b"1.1 proxy");
+    headers.insert("Connection", "close");
+    assert_eq!(headers[3].key, "Connection");
+}
+
+#[test]
+fn headers_remove_all() {
+    let mut headers = Headers::new();
+    headers.append("A", "1");
+    headers.append("B", "2");
+    headers.append("a", "3");
+    assert_eq!(headers.remove("A"), 2);
+    assert_eq!(headers.remove("A"), 0);
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers[0].key, "B");
+}
+
+// ************URI TEST*************//
+
+req! {
+    uri_origin_form,
+    "GET /search?q=rust&lang=en HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.path.form(), UriForm::Origin);
+        assert_eq!(req.path.host(), None);
+        assert_eq!(req.path.path(), "/search");
+        assert_eq!(req.path.query(), Some("q=rust&lang=en"));
+    }
+}
+
+req! {
+    uri_absolute_form,
+    "GET http://user:pw@example.org:8080/a/b?c=d HTTP/1.1\r\nHost: example.org:8080\r\n\r\n",
+    |req| {
+        assert_eq!(req.path.form(), UriForm::Absolute);
+        assert_eq!(req.path.scheme(), Some("http"));
+        assert_eq!(req.path.userinfo(), Some("user:pw"));
+        assert_eq!(req.path.host(), Some("example.org"));
+        assert_eq!(req.path.port(), Some(8080));
+        assert_eq!(req.path.path(), "/a/b");
+        assert_eq!(req.path.query(), Some("c=d"));
+        assert_eq!(req.path, "http://user:pw@example.org:8080/a/b?c=d");
+    }
+}
+
+req! {
+    uri_absolute_form_without_path,
+    "GET https://example.org?x HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.path.scheme(), Some("https"));
+        assert_eq!(req.path.host(), Some("example.org"));
+        assert_eq!(req.path.path(), "");
+        assert_eq!(req.path.query(), Some("x"));
+    }
+}
+
+req! {
+    uri_authority_form,
+    "CONNECT [::1]:443 HTTP/1.1\r\nHost: [::1]:443\r\n\r\n",
+    |req| {
+        assert_eq!(req.path.form(), UriForm::Authority);
+        assert_eq!(req.path.host(), Some("[::1]"));
+        assert_eq!(req.path.port(), Some(443));
+        assert_eq!(req.path, "[::1]:443");
+    }
+}
+
+req! {
+    uri_asterisk_form,
+    "OPTIONS * HTTP/1.1\r\nHost: example.org\r\n\r\n",
+    |req| {
+        assert_eq!(req.path.form(), UriForm::Asterisk);
+        assert_eq!(req.path.host(), None);
+    }
+}
+
+#[test]
+fn uri_invalid_port() {
+    let parse = |buf: &[u8]| Request::parse(buf).err();
+    assert_eq!(
+        parse(b"GET http://a:80x/ HTTP/1.1\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Uri)
+    );
+    assert_eq!(
+        parse(b"GET http://a:70000/ HTTP/1.1\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Uri)
+    );
+    assert_eq!(
+        parse(b"CONNECT a:https HTTP/1.1\r\nHost: a\r\n\r\n"),
+        Some(ParseError::Uri)
+    );
+}
+
+#[test]
+fn modify_host_structural() {
+    let mut req =
+        Request::parse(b"GET http://a.org:8080/x/y?z HTTP/1.1\r\nHost: a.org\r\n\r\n").unwrap();
+    req.modify_host("b.org");
+    assert_eq!(req.path, "http://b.org:8080/x/y?z");
+    assert_eq!(req.headers.get("host"), Some(&b"b.org:8080"[..]));
+    let mut req = Request::parse(b"GET http://a.org HTTP/1.1\r\nHost: a.org\r\n\r\n").unwrap();
+    req.modify_host("b.org");
+    assert_eq!(req.path, "http://b.org");
+    // origin-form path is short, it used to be sliced at [7..]
+    let mut req = Request::parse(b"GET /x HTTP/1.1\r\nHost: a.org\r\n\r\n").unwrap();
+    req.modify_host("b.org");
+    assert_eq!(req.path, "/x");
+    assert_eq!(req.host, "b.org");
+}
+
+// ************PORT TEST*************//
+
+req! {
+    port_from_absolute_url,
+    "GET http://example.test:8081/ HTTP/1.1\r\nHost: example.test:8081\r\n\r\n",
+    |req| {
+        assert_eq!(req.host, "example.test");
+        assert_eq!(req.port, 8081);
+    }
+}
+
+req! {
+    port_absolute_url_over_host_header,
+    "GET http://example.test:8081/ HTTP/1.1\r\nHost: other.test:9090\r\n\r\n",
+    |req| {
+        assert_eq!(req.host, "example.test");
+        assert_eq!(req.port, 8081);
+        assert_eq!(req.headers.get("Host"), Some(&b"example.test:8081"[..]));
+    }
+}
+
+req! {
+    port_from_host_header,
+    "GET /index.html HTTP/1.1\r\nHost: [::1]:8081\r\n\r\n",
+    |req| {
+        assert_eq!(req.host, "[::1]");
+        assert_eq!(req.port, 8081);
+    }
+}
+
+req! {
+    port_default_of_scheme,
+    "GET https://example.test/ HTTP/1.1\r\nHost: example.test\r\n\r\n",
+    |req| {
+        assert_eq!(req.port, 443);
+    }
+}
+
+#[test]
+fn scheme_not_http() {
+    let check = |buf: &str| Request::parse(buf.as_bytes()).unwrap().check_scheme();
+    assert_eq!(
+        check("GET HTTP://a.test/ HTTP/1.1\r\nHost: a.test\r\n\r\n"),
+        Ok(())
+    );
+    assert_eq!(check("GET / HTTP/1.1\r\nHost: a.test\r\n\r\n"), Ok(()));
+    assert_eq!(
+        check("CONNECT a.test:443 HTTP/1.1\r\nHost: a.test\r\n\r\n"),
+        Ok(())
+    );
+    let https = "GET https://a.test/ HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(check(https), Err(ParseError::Scheme));
+    let ftp = "GET ftp://a.test/ HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(check(ftp), Err(ParseError::Scheme));
+    // rewrite can't send request to https either
+    let mut req = Request::parse(b"GET /x HTTP/1.1\r\nHost: a.test\r\n\r\n").unwrap();
+    assert_eq!(req.set_url("https://b.test/x"), Err(ParseError::Scheme));
+    assert_eq!(req.port, 80);
+    let mut req = Request::parse(b"CONNECT a.test:443 HTTP/1.1\r\nHost: a.test\r\n\r\n").unwrap();
+    assert_eq!(req.set_url("https://b.test/"), Ok(()));
+    assert_eq!(req.host, "b.test");
+}
+
+req! {
+    port_default_connect,
+    "CONNECT example.test HTTP/1.1\r\nHost: example.test\r\n\r\n",
+    |req| {
+        assert_eq!(req.host, "example.test");
+        assert_eq!(req.port, 443);
+    }
+}
+
+#[test]
+fn many_host_headers() {
+    let req = Request::parse(b"GET / HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n");
+    assert_eq!(req.err(), Some(ParseError::ManyHost));
+    let req = Request::parse(b"GET http://a/ HTTP/1.1\r\nHost: a\r\nHost: a\r\n\r\n");
+    assert_eq!(req.err(), Some(ParseError::ManyHost));
+}
+
+#[test]
+fn port_invalid_host_header() {
+    let req = Request::parse(b"GET / HTTP/1.1\r\nHost: a:b\r\n\r\n");
+    assert_eq!(req.err(), Some(ParseError::Host));
+}
+
+#[test]
+fn modify_host_with_port() {
+    let mut req = Request::parse(b"GET http://a.org/x HTTP/1.1\r\nHost: a.org\r\n\r\n").unwrap();
+    req.modify_host("b.org:8080");
+    assert_eq!(req.path, "http://b.org:8080/x");
+    assert_eq!(req.port, 8080);
+    assert_eq!(req.headers.get("Host"), Some(&b"b.org:8080"[..]));
+    // back to default port, Host don't need it
+    req.modify_host("c.org:80");
+    assert_eq!(req.path, "http://c.org:80/x");
+    assert_eq!(req.headers.get("Host"), Some(&b"c.org"[..]));
+}
+
+// ************URL TEST*************//
+
+req! {
+    url_origin_form,
+    "GET /a/b?x=1 HTTP/1.1\r\nHost: Example.test\r\n\r\n",
+    |req| {
+        assert_eq!(req.url(), "http://Example.test/a/b?x=1");
+    }
+}
+
+req! {
+    url_absolute_form,
+    "GET HTTPS://example.test:8443 HTTP/1.1\r\nHost: example.test\r\n\r\n",
+    |req| {
+        assert_eq!(req.url(), "https://example.test:8443/");
+    }
+}
+
+req! {
+    url_connect,
+    "CONNECT example.test:443 HTTP/1.1\r\nHost: example.test:443\r\n\r\n",
+    |req| {
+        assert_eq!(req.url(), "https://example.test/");
+    }
+}
+
+req! {
+    url_asterisk,
+    "OPTIONS * HTTP/1.1\r\nHost: example.test:8080\r\n\r\n",
+    |req| {
+        assert_eq!(req.url(), "http://example.test:8080/");
+    }
+}
+
+// ************REWRITE TEST*************//
+
+// request line and headers as they are sent to server
+#[cfg(test)]
+fn written(req: &Request) -> String {
+    let mut buf = Vec::new();
+    req.write(&mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+#[test]
+fn rewrite_capture_group() {
+    let re = Regex::new(r"^http://old\.test/api/v1/(.*)$").unwrap();
+    let replace = "http://new.test:8080/v2/$1";
+    let mut req =
+        Request::parse(b"GET http://old.test/api/v1/users?id=7 HTTP/1.1\r\nHost: old.test\r\n\r\n")
+            .unwrap();
+    assert_eq!(req.rewrite(&re, replace), Ok(true));
+    assert_eq!(
+        written(&req),
+        "GET http://new.test:8080/v2/users?id=7 HTTP/1.1\r\nHost: new.test:8080\r\n\r\n"
+    );
+    assert_eq!(req.host, "new.test");
+    assert_eq!(req.port, 8080);
+    // origin-form keeps its form
+    let mut req = Request::parse(b"GET /api/v1/a/b HTTP/1.1\r\nHost: old.test\r\n\r\n").unwrap();
+    assert_eq!(req.rewrite(&re, replace), Ok(true));
+    assert_eq!(
+        written(&req),
+        "GET /v2/a/b HTTP/1.1\r\nHost: new.test:8080\r\n\r\n"
+    );
+}
+
+#[test]
+fn rewrite_host_and_query() {
+    // swap host and put path into query
+    let re = Regex::new(r"^http://(?P<sub>[a-z]+)\.example\.test/(.*)$").unwrap();
+    let mut req = Request::parse(
+        b"POST http://shop.example.test/cart HTTP/1.1\r\nHOST: shop.example.test\r\nContent-Length: 2\r\n\r\nab",
+    )
+    .unwrap();
+    assert_eq!(
+        req.rewrite(&re, "http://example.test/${sub}?page=$2"),
+        Ok(true)
+    );
+    assert_eq!(
+        written(&req),
+        "POST http://example.test/shop?page=cart HTTP/1.1\r\nHOST: example.test\r\nContent-Length: 2\r\n\r\nab"
+    );
+    // back to default port and no path
+    let re = Regex::new(r"^http://example\.test/.*").unwrap();
+    assert_eq!(req.rewrite(&re, "http://other.test"), Ok(true));
+    assert_eq!(req.path, "http://other.test");
+    assert_eq!(req.port, 80);
+}
+
+#[test]
+fn rewrite_no_match() {
+    let re = Regex::new(r"^http://old\.test/").unwrap();
+    let buf = b"GET /x HTTP/1.1\r\nHost: new.test\r\n\r\n";
+    let mut req = Request::parse(buf).unwrap();
+    assert_eq!(req.rewrite(&re, "http://a.test/"), Ok(false));
+    assert_eq!(written(&req).as_bytes(), &buf[..]);
+}
+
+#[test]
+fn rewrite_connect_and_invalid() {
+    let re = Regex::new(r"^https://old\.test/").unwrap();
+    let mut req =
+        Request::parse(b"CONNECT old.test:443 HTTP/1.1\r\nHost: old.test:443\r\n\r\n").unwrap();
+    assert_eq!(req.rewrite(&re, "https://new.test:8443/"), Ok(true));
+    assert_eq!(
+        written(&req),
+        "CONNECT new.test:8443 HTTP/1.1\r\nHost: new.test:8443\r\n\r\n"
+    );
+    // result must be an absolute url
+    let re = Regex::new(r"^https://new\.test:8443/").unwrap();
+    assert_eq!(req.rewrite(&re, "/relative"), Err(ParseError::Uri));
+    assert_eq!(req.host, "new.test");
+}
+
+#[test]
+fn headers_remove_hop_by_hop() {
+    let mut req = Request::parse(
+        b"GET http://a/ HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, X-Secret,content-length\r\n\
+          Proxy-Connection: keep-alive\r\nKeep-Alive: timeout=5\r\nProxy-Authorization: Basic eDp5\r\n\
+          X-Secret: 1\r\nTE: trailers\r\nUpgrade: h2c\r\nAccept: */*\r\nContent-Length: 0\r\n\r\n",
+    )
+    .unwrap();
+    req.headers.remove_hop_by_hop();
+    assert_eq!(
+        req.headers.to_string(),
+        "Host: a\r\nAccept: */*\r\nContent-Length: 0\r\n"
+    );
+    let mut res = Response::parse(
+        b"HTTP/1.1 200 OK\r\nconnection: close\r\nProxy-Authenticate: Basic\r\n\
+          Transfer-Encoding: chunked\r\nTrailer: X-Sum\r\n\r\n",
+    )
+    .unwrap();
+    res.headers.remove_hop_by_hop();
+    assert_eq!(res.headers.to_string(), "Transfer-Encoding: chunked\r\n");
+}
+
+#[test]
+fn date_formats() {
+    assert_eq!(
+        parse_date(b"Sun, 06 Nov 1994 08:49:37 GMT"),
+        Some(784111777)
+    );
+    assert_eq!(
+        parse_date(b"Sunday, 06-Nov-94 08:49:37 GMT"),
+        Some(784111777)
+    );
+    assert_eq!(parse_date(b"Sun Nov  6 08:49:37 1994"), Some(784111777));
+    assert_eq!(parse_date(b"Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+    assert_eq!(
+        parse_date(b"Tue, 29 Feb 2000 12:00:00 GMT"),
+        Some(951825600)
+    );
+    assert_eq!(parse_date(b"0"), None);
+    assert_eq!(parse_date(b"Sun, 06 Foo 1994 08:49:37 GMT"), None);
+    assert_eq!(parse_date(b"Sun, 06 Nov 1994 25:49:37 GMT"), None);
+}
+
+#[test]
+fn request_expect_continue() {
+    let check = |version: &str, headers: &str| {
+        let raw = format!("POST / {}\r\nHost: a\r\n{}\r\n", version, headers);
+        Request::parse(raw.as_bytes()).unwrap().expect_continue()
+    };
+    assert!(check("HTTP/1.1", "Expect: 100-continue\r\n"));
+    assert!(check("HTTP/1.1", "Expect: 100-Continue \r\n"));
+    assert!(!check("HTTP/1.0", "Expect: 100-continue\r\n"));
+    assert!(!check("HTTP/1.1", "Expect: foo\r\n"));
+    assert!(!check("HTTP/1.1", ""));
+}
+
+#[test]
+fn request_not_modified() {
+    let check = |headers: &str, etag: Option<&[u8]>, last_modified: Option<&[u8]>| {
+        let raw = format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n", headers);
+        Request::parse(raw.as_bytes())
+            .unwrap()
+            .not_modified(etag, last_modified)
+    };
+    let etag = Some(&b"\"2\""[..]);
+    let date = Some(&b"Sun, 06 Nov 1994 08:49:37 GMT"[..]);
+    assert!(check("If-None-Match: \"1\", W/\"2\"\r\n", etag, None));
+    assert!(check("If-None-Match: *\r\n", etag, None));
+    assert!(check("If-None-Match: *\r\n", None, None));
+    assert!(!check("If-None-Match: \"1\"\r\n", etag, date));
+    assert!(!check("If-None-Match: \"2\"\r\n", None, date));
+    // If-Modified-Since is ignored when If-None-Match is sent
+    let since = "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+    assert!(check(since, etag, date));
+    assert!(!check(
+        &format!("If-None-Match: \"1\"\r\n{}", since),
+        etag,
+        date
+    ));
+    let old = Some(&b"Sat, 05 Nov 1994 08:49:37 GMT"[..]);
+    assert!(check(since, None, old));
+    let new = Some(&b"Mon, 07 Nov 1994 08:49:37 GMT"[..]);
+    assert!(!check(since, None, new));
+    assert!(!check("", etag, date));
+}
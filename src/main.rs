@@ -2,10 +2,21 @@
 extern crate serde_derive;
 #[macro_use]
 extern crate log;
+mod auth;
+mod cache;
+mod cassette;
 mod config;
+mod disk;
+mod error;
+mod filter;
 mod handle;
+mod header;
 mod http;
+mod pool;
+mod rule;
 mod threadpool;
+mod user;
+use crate::cassette::Mode;
 use crate::config::Config;
 use crate::handle::handle_client;
 use crate::threadpool::ThreadPool;
@@ -41,6 +52,24 @@ fn main() -> io::Result<()> {
         ),
     ])
     .unwrap();
+    info!(
+        "{} website rules in blacklist, {} unsupported rules ignored",
+        config.filter.blocked.len(),
+        config.filter.blocked.skipped()
+    );
+    if let Some((recovered, discarded)) = config.cache.as_ref().and_then(|x| x.recovered()) {
+        info!(
+            "{} responses in cache directory, {} broken files removed",
+            recovered, discarded
+        );
+    }
+
+    if let Some(cassette) = &config.cassette {
+        info!("cassette {}, mode: {:?}", cassette.path, cassette.mode);
+        if cassette.mode == Mode::Replay {
+            info!("{} responses in cassette", cassette.recorded());
+        }
+    }
 
     // start thread pool
     let pool = ThreadPool::new(config.thread);
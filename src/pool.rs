@@ -0,0 +1,245 @@
+use crate::error::Error;
+use crate::http::{copy_body_inspect, read_head, BodyLength, Request};
+use std::collections::HashMap;
+use std::io;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// timeout of server read operation
+// server may close a keep-alive connection silently, don't wait forever
+const SERVER_TIMEOUT: u64 = 30000;
+// timeout of connecting to server
+const CONNECT_TIMEOUT: u64 = 10000;
+
+// idle keep-alive connections to servers, shared by all clients
+// [pool]
+// max_idle=8
+// idle_timeout=60
+#[derive(Deserialize)]
+pub struct Pool {
+    // idle connections kept for each server, 0 closes connection after every response
+    #[serde(default = "default_max_idle")]
+    pub max_idle: usize,
+    // seconds an idle connection is kept
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    // "host:port" -> idle connections, the last one is the latest
+    #[serde(skip)]
+    idle: Mutex<HashMap<String, Vec<Idle>>>,
+}
+
+fn default_max_idle() -> usize {
+    8
+}
+
+fn default_idle_timeout() -> u64 {
+    60
+}
+
+impl Default for Pool {
+    fn default() -> Pool {
+        Pool {
+            max_idle: default_max_idle(),
+            idle_timeout: default_idle_timeout(),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+struct Idle {
+    server: Server,
+    since: Instant,
+}
+
+impl Pool {
+    // an idle connection to "host:port" that can still be used, the latest one first
+    pub fn take(&self, host: &str) -> Option<Server> {
+        let timeout = Duration::from_secs(self.idle_timeout);
+        loop {
+            // look at the socket after lock is released, other threads don't wait for it
+            let conn = {
+                let mut idle = self.idle.lock().unwrap();
+                let list = idle.get_mut(host)?;
+                let conn = list.pop();
+                if list.is_empty() {
+                    idle.remove(host);
+                }
+                conn?
+            };
+            if conn.since.elapsed() < timeout && conn.server.usable() && conn.server.alive() {
+                return Some(conn.server);
+            }
+            debug!("idle connection to {} is closed", host);
+        }
+    }
+
+    // keep a connection for the next request to the same server
+    // connection must be at the end of a response
+    pub fn put(&self, server: Server) {
+        if self.max_idle == 0 || !server.usable() {
+            return;
+        }
+        let timeout = Duration::from_secs(self.idle_timeout);
+        let mut idle = self.idle.lock().unwrap();
+        // connections idle for too long are closed, whatever server they are to
+        idle.retain(|_, list| {
+            list.retain(|conn| conn.since.elapsed() < timeout);
+            !list.is_empty()
+        });
+        let list = idle.entry(server.host.clone()).or_default();
+        // the oldest one is closed first
+        if list.len() >= self.max_idle {
+            list.remove(0);
+        }
+        list.push(Idle {
+            server,
+            since: Instant::now(),
+        });
+    }
+
+    // number of idle connections to "host:port"
+    #[cfg(test)]
+    fn idle(&self, host: &str) -> usize {
+        self.idle
+            .lock()
+            .unwrap()
+            .get(host)
+            .map_or(0, |list| list.len())
+    }
+}
+
+// keep-alive connection to server
+pub struct Server {
+    // "host:port", only requests to the same server can use this connection
+    host: String,
+    pub reader: BufReader<TcpStream>,
+    stream: TcpStream,
+    // server will close connection after this time
+    pub expire: Option<Instant>,
+    // number of requests server allow us to send
+    pub remain: Option<u64>,
+}
+
+impl Server {
+    pub fn connect(host: &str) -> Result<Server, Error> {
+        let stream = connect(host)?;
+        let server_error = |e| Error::Connect(host.to_owned(), e);
+        stream
+            .set_read_timeout(Some(Duration::from_millis(SERVER_TIMEOUT)))
+            .map_err(server_error)?;
+        let reader = BufReader::new(stream.try_clone().map_err(server_error)?);
+        Ok(Server {
+            host: host.to_owned(),
+            reader,
+            stream,
+            expire: None,
+            remain: None,
+        })
+    }
+
+    // server still lets us send requests
+    fn usable(&self) -> bool {
+        self.expire.is_none_or(|expire| Instant::now() < expire)
+            && self.remain.is_none_or(|remain| remain > 0)
+    }
+
+    // server may close idle connection, or send something we didn't ask for
+    // neither can be used, look at the socket without waiting
+    fn alive(&self) -> bool {
+        if !self.reader.buffer().is_empty() || self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0; 1];
+        let idle = match self.stream.peek(&mut byte) {
+            Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+            // 0 is closed connection
+            Ok(_) => false,
+        };
+        idle && self.stream.set_nonblocking(false).is_ok()
+    }
+
+    // send request and read header of response
+    // request body is shown to `inspect`, see copy_body_inspect()
+    pub fn send<F: FnMut(&[u8])>(
+        &mut self,
+        req: &Request,
+        length: BodyLength,
+        client: &mut BufReader<TcpStream>,
+        res_buffer: &mut Vec<u8>,
+        inspect: F,
+    ) -> io::Result<usize> {
+        req.write(&mut self.stream)?;
+        // request body is not in header, copy it from client
+        copy_body_inspect(client, &mut self.stream, length, inspect)?;
+        read_head(&mut self.reader, res_buffer)
+    }
+}
+
+// resolve "host:port" and connect to it
+pub fn connect(host: &str) -> Result<TcpStream, Error> {
+    // to_socket_addrs() will resole host to ip address
+    let addr = host
+        .to_socket_addrs()
+        .map_err(|e| Error::Resolve(host.to_owned(), e))?
+        .next()
+        .ok_or_else(|| {
+            let e = io::Error::new(io::ErrorKind::NotFound, "no result found");
+            Error::Resolve(host.to_owned(), e)
+        })?;
+    TcpStream::connect_timeout(&addr, Duration::from_millis(CONNECT_TIMEOUT))
+        .map_err(|e| Error::Connect(host.to_owned(), e))
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn test_server() -> (std::net::TcpListener, String) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    (listener, host)
+}
+
+#[test]
+fn pool_take_and_put() {
+    let pool: Pool = toml::from_str("max_idle = 2").unwrap();
+    let (listener, host) = test_server();
+    let mut accepted = Vec::new();
+    for _ in 0..3 {
+        pool.put(Server::connect(&host).unwrap());
+        accepted.push(listener.accept().unwrap().0);
+    }
+    // the oldest one is closed
+    assert_eq!(pool.idle(&host), 2);
+    assert!(pool.take("127.0.0.1:1").is_none());
+    assert!(pool.take(&host).is_some());
+    assert!(pool.take(&host).is_some());
+    assert!(pool.take(&host).is_none());
+    // server has closed its connection
+    let mut server = Server::connect(&host).unwrap();
+    server.remain = Some(5);
+    pool.put(server);
+    drop(listener.accept().unwrap());
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(pool.idle(&host), 1);
+    assert!(pool.take(&host).is_none());
+    assert_eq!(pool.idle(&host), 0);
+    // server doesn't allow more requests
+    let mut server = Server::connect(&host).unwrap();
+    server.remain = Some(0);
+    pool.put(server);
+    assert_eq!(pool.idle(&host), 0);
+}
+
+#[test]
+fn pool_idle_timeout() {
+    let pool: Pool = toml::from_str("idle_timeout = 0").unwrap();
+    let (listener, host) = test_server();
+    pool.put(Server::connect(&host).unwrap());
+    let _accepted = listener.accept().unwrap();
+    assert!(pool.take(&host).is_none());
+    let pool: Pool = toml::from_str("max_idle = 0").unwrap();
+    pool.put(Server::connect(&host).unwrap());
+    assert_eq!(pool.idle(&host), 0);
+}
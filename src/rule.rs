@@ -0,0 +1,287 @@
+use crate::filter::DomainMatcher;
+use crate::http::Request;
+use crate::user::{Identity, Selector};
+use regex::Regex;
+
+// rule of url in config.toml
+// every condition given must match, a rule without condition match everything
+// [[filter.rule]]
+// method=["POST", "PUT"]
+// host="*.example.com"
+// path="/admin"
+// path_regex="^/api/v[0-9]+/"
+// query=["debug", "id=42"]
+// groups=["interns"]
+// action="deny"
+// status=403
+#[derive(Deserialize)]
+pub struct UrlRule {
+    #[serde(default)]
+    pub method: Vec<String>,
+    // syntax of DomainMatcher
+    pub host: Option<String>,
+    // prefix of path
+    pub path: Option<String>,
+    pub path_regex: Option<String>,
+    // "key" if parameter is present, "key=value" if it has this value
+    #[serde(default)]
+    pub query: Vec<String>,
+    pub action: RuleAction,
+    // status code of deny, 403 by default
+    pub status: Option<u16>,
+    // url of redirect
+    pub location: Option<String>,
+    // name of tag
+    pub tag: Option<String>,
+    // users and groups it is for, everyone by default
+    #[serde(flatten)]
+    pub who: Selector,
+    // host and path_regex above, built when config is opened
+    #[serde(skip)]
+    host_matcher: Option<DomainMatcher>,
+    #[serde(skip)]
+    path_matcher: Option<Regex>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    // forward it without checking blocklist
+    Allow,
+    // answer it with an error page
+    Deny,
+    // answer it with a redirection to location
+    Redirect,
+    // write tag in log and check next rule
+    Tag,
+}
+
+// what to do with a request
+#[derive(PartialEq, Debug)]
+pub enum Verdict<'a> {
+    Allow,
+    Deny(u16),
+    Redirect(&'a str),
+    // no rule decides, go on to blocklist
+    Continue,
+}
+
+impl UrlRule {
+    // check and compile rule, error message is shown when config is opened
+    pub fn load(&mut self) -> Result<(), String> {
+        if let Some(host) = &self.host {
+            let matcher = DomainMatcher::new(&[host]).map_err(|e| e.to_string())?;
+            self.host_matcher = Some(matcher);
+        }
+        if let Some(re) = &self.path_regex {
+            self.path_matcher = Some(Regex::new(re).map_err(|e| e.to_string())?);
+        }
+        match self.action {
+            RuleAction::Deny => match self.status {
+                Some(status) if !(400..600).contains(&status) => {
+                    return Err(format!("deny rule with status {}", status))
+                }
+                _ => {}
+            },
+            RuleAction::Redirect if self.location.is_none() => {
+                return Err("redirect rule without location".to_owned())
+            }
+            RuleAction::Tag if self.tag.is_none() => return Err("tag rule without tag".to_owned()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_match(&self, req: &Request, who: &Identity) -> bool {
+        if !self.who.is_match(who) {
+            return false;
+        }
+        let method = |m: &String| m.eq_ignore_ascii_case(req.method);
+        if !self.method.is_empty() && !self.method.iter().any(method) {
+            return false;
+        }
+        if let Some(host) = &self.host_matcher {
+            if !host.is_match(&req.host) {
+                return false;
+            }
+        }
+        let path = req.path.path();
+        if let Some(prefix) = &self.path {
+            if !path.starts_with(&prefix[..]) {
+                return false;
+            }
+        }
+        if let Some(re) = &self.path_matcher {
+            if !re.is_match(path) {
+                return false;
+            }
+        }
+        let query = req.path.query().unwrap_or("");
+        self.query.iter().all(|param| has_param(query, param))
+    }
+}
+
+// "key" match "a=1&key&b=2" and "key=", "key=1" only match "key=1"
+fn has_param(query: &str, param: &str) -> bool {
+    let (key, value) = match param.find('=') {
+        Some(pos) => (&param[..pos], Some(&param[pos + 1..])),
+        None => (param, None),
+    };
+    query.split('&').any(|pair| {
+        let (k, v) = match pair.find('=') {
+            Some(pos) => (&pair[..pos], &pair[pos + 1..]),
+            None => (pair, ""),
+        };
+        k == key && value.is_none_or(|value| value == v)
+    })
+}
+
+// rules are checked in order, the first allow, deny or redirect rule decides
+pub fn check<'a>(rules: &'a [UrlRule], req: &Request, who: &Identity) -> Verdict<'a> {
+    for rule in rules.iter().filter(|rule| rule.is_match(req, who)) {
+        match rule.action {
+            RuleAction::Allow => return Verdict::Allow,
+            RuleAction::Deny => return Verdict::Deny(rule.status.unwrap_or(403)),
+            RuleAction::Redirect => {
+                return Verdict::Redirect(rule.location.as_ref().map_or("", |x| &x[..]))
+            }
+            RuleAction::Tag => {
+                let tag = rule.tag.as_ref().map_or("", |x| &x[..]);
+                let user = who.name.as_ref().map_or("-", |x| &x[..]);
+                info!("TAG {}: {} {} {}", tag, user, req.method, req.url());
+            }
+        }
+    }
+    Verdict::Continue
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+fn rules(toml: &str) -> Vec<UrlRule> {
+    crate::config::test_rules(toml, UrlRule::load).unwrap()
+}
+
+#[cfg(test)]
+fn verdict(rules: &[UrlRule], req: &str) -> String {
+    verdict_of(rules, req, &Identity::default())
+}
+
+#[cfg(test)]
+fn verdict_of(rules: &[UrlRule], req: &str, who: &Identity) -> String {
+    let req = Request::parse(req.as_bytes()).unwrap();
+    format!("{:?}", check(rules, &req, who))
+}
+
+#[test]
+fn rule_conditions() {
+    let rules = rules(
+        r#"
+        [[rule]]
+        method = ["post"]
+        host = ".example.com"
+        path = "/admin"
+        action = "deny"
+        [[rule]]
+        path_regex = "^/api/v[0-9]+/"
+        query = ["debug", "id=42"]
+        action = "deny"
+        status = 404
+        "#,
+    );
+    let get = "GET /admin/x HTTP/1.1\r\nHost: www.example.com\r\n\r\n";
+    assert_eq!(verdict(&rules, get), "Continue");
+    let post = "POST /admin/x HTTP/1.1\r\nHost: www.example.com\r\n\r\n";
+    assert_eq!(verdict(&rules, post), "Deny(403)");
+    let other = "POST /admin/x HTTP/1.1\r\nHost: example.org\r\n\r\n";
+    assert_eq!(verdict(&rules, other), "Continue");
+    let api = "GET /api/v2/x?id=42&debug HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(verdict(&rules, api), "Deny(404)");
+    let api = "GET /api/v2/x?id=4&debug=1 HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(verdict(&rules, api), "Continue");
+    let api = "GET /api/x?id=42&debug HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(verdict(&rules, api), "Continue");
+}
+
+#[test]
+fn rule_order() {
+    let rules = rules(
+        r#"
+        [[rule]]
+        path = "/"
+        action = "tag"
+        tag = "all"
+        [[rule]]
+        host = "good.test"
+        action = "allow"
+        [[rule]]
+        path = "/old"
+        action = "redirect"
+        location = "http://new.test/"
+        [[rule]]
+        action = "deny"
+        status = 410
+        "#,
+    );
+    let good = "GET /old HTTP/1.1\r\nHost: good.test\r\n\r\n";
+    assert_eq!(verdict(&rules, good), "Allow");
+    let old = "GET /old/page HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(verdict(&rules, old), "Redirect(\"http://new.test/\")");
+    let other = "GET /x HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    assert_eq!(verdict(&rules, other), "Deny(410)");
+}
+
+#[test]
+fn rule_users() {
+    let rules = rules(
+        r#"
+        [[rule]]
+        groups = ["admins"]
+        action = "allow"
+        [[rule]]
+        host = "social.test"
+        users = ["bob"]
+        groups = ["interns"]
+        action = "deny"
+        "#,
+    );
+    let who = |name: &str, groups: &[&str]| Identity {
+        name: Some(name.to_owned()),
+        groups: groups.iter().map(|x| x.to_string()).collect(),
+    };
+    let req = "GET / HTTP/1.1\r\nHost: social.test\r\n\r\n";
+    assert_eq!(verdict(&rules, req), "Continue");
+    assert_eq!(verdict_of(&rules, req, &who("bob", &[])), "Deny(403)");
+    assert_eq!(
+        verdict_of(&rules, req, &who("dave", &["interns"])),
+        "Deny(403)"
+    );
+    assert_eq!(
+        verdict_of(&rules, req, &who("dave", &["admins", "interns"])),
+        "Allow"
+    );
+    assert_eq!(
+        verdict_of(&rules, req, &who("erin", &["staff"])),
+        "Continue"
+    );
+}
+
+#[test]
+fn rule_invalid() {
+    let load = |toml: &str| crate::config::test_rules(toml, UrlRule::load);
+    assert!(load("[[rule]]\naction = \"redirect\"").is_err());
+    assert!(load("[[rule]]\naction = \"tag\"").is_err());
+    assert!(load("[[rule]]\naction = \"deny\"\nstatus = 200").is_err());
+    assert!(load("[[rule]]\naction = \"deny\"\npath_regex = \"(\"").is_err());
+    assert!(load("[[rule]]\naction = \"deny\"\nstatus = 451").is_ok());
+}
+
+#[test]
+fn query_param() {
+    assert!(has_param("a=1&key&b=2", "key"));
+    assert!(has_param("key=", "key"));
+    assert!(has_param("key=1", "key=1"));
+    assert!(!has_param("key=12", "key=1"));
+    assert!(!has_param("keys=1", "key"));
+    assert!(!has_param("", "key"));
+}
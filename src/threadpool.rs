@@ -1,5 +1,6 @@
 // The code for this module comes from `The Rust Programming Language`
 // You can find introduction of this code in chapter 20-2 and 20-3
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 pub struct ThreadPool {
@@ -53,7 +54,7 @@ impl<F: FnOnce()> FnBox for F {
     }
 }
 
-type Job = Box<FnBox + Send + 'static>;
+type Job = Box<dyn FnBox + Send + 'static>;
 
 enum Message {
     NewJob(Job),
@@ -69,11 +70,19 @@ impl Worker {
     fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
         info!("Starting worker {}.", id);
         let thread = Some(thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+            // lock is never held while running job, but don't stop if it is poisoned
+            let message = receiver
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .recv()
+                .unwrap();
             trace!("Worker {} got workload", id);
             match message {
                 Message::NewJob(job) => {
-                    job.call_box();
+                    // a panic in job only end that job, not the worker thread
+                    if panic::catch_unwind(AssertUnwindSafe(|| job.call_box())).is_err() {
+                        error!("Worker {} workload panicked", id);
+                    }
                 }
                 Message::Terminate => {
                     info!("Worker {} is terminated.", id);
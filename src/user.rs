@@ -0,0 +1,176 @@
+use crate::config::Cidr;
+use std::net::IpAddr;
+
+// users and groups in config.toml
+// a client is the user it logs in as, or the user of its address without login
+// [[user]]
+// name="printer"
+// ip=["10.0.0.5"]
+// [[group]]
+// name="interns"
+// users=["bob", "printer"]
+#[derive(Deserialize)]
+pub struct IpUser {
+    pub name: String,
+    pub ip: Vec<Cidr>,
+}
+
+#[derive(Deserialize)]
+pub struct Group {
+    pub name: String,
+    // login names, or names of users above
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+// who sends a request
+#[derive(Default, Debug)]
+pub struct Identity {
+    // None if client doesn't log in and its address is unknown
+    pub name: Option<String>,
+    pub groups: Vec<String>,
+}
+
+// users and groups a rule is for, in config.toml
+// users=["alice"]
+// groups=["interns"]
+// rule is for everyone if both are empty
+#[derive(Deserialize, Default)]
+pub struct Selector {
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl Selector {
+    pub fn is_everyone(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+
+    pub fn is_match(&self, who: &Identity) -> bool {
+        if self.is_everyone() {
+            return true;
+        }
+        let user = who
+            .name
+            .as_ref()
+            .is_some_and(|name| self.users.contains(name));
+        user || who.groups.iter().any(|group| self.groups.contains(group))
+    }
+
+    // every group must be in config, a typo would make rule match nobody
+    pub fn check(&self, groups: &[Group]) -> Result<(), String> {
+        match self
+            .groups
+            .iter()
+            .find(|name| !groups.iter().any(|g| &g.name == *name))
+        {
+            Some(name) => Err(format!("unknown group {}", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+// login name wins over address
+pub fn identify(users: &[IpUser], groups: &[Group], login: Option<String>, ip: IpAddr) -> Identity {
+    let name = login.or_else(|| {
+        users
+            .iter()
+            .find(|user| user.ip.iter().any(|cidr| cidr.contains(ip)))
+            .map(|user| user.name.clone())
+    });
+    let groups = match &name {
+        Some(name) => groups
+            .iter()
+            .filter(|group| group.users.contains(name))
+            .map(|group| group.name.clone())
+            .collect(),
+        None => Vec::new(),
+    };
+    Identity { name, groups }
+}
+
+// ************TEST*************//
+
+#[cfg(test)]
+#[derive(Deserialize)]
+struct TestUsers {
+    user: Vec<IpUser>,
+    group: Vec<Group>,
+    rule: Vec<Selector>,
+}
+
+#[cfg(test)]
+fn test_users() -> TestUsers {
+    toml::from_str(
+        r#"
+        [[user]]
+        name = "printer"
+        ip = ["10.0.0.5", "10.0.1.0/24"]
+        [[group]]
+        name = "interns"
+        users = ["bob", "printer"]
+        [[group]]
+        name = "admins"
+        users = ["alice"]
+        [[rule]]
+        [[rule]]
+        users = ["alice"]
+        [[rule]]
+        groups = ["interns"]
+        [[rule]]
+        users = ["carol"]
+        groups = ["admins"]
+        "#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn identify_user() {
+    let users = test_users();
+    let ip = |s: &str| s.parse().unwrap();
+    let who = identify(
+        &users.user,
+        &users.group,
+        Some("bob".to_owned()),
+        ip("10.0.0.5"),
+    );
+    assert_eq!(who.name.as_deref(), Some("bob"));
+    assert_eq!(who.groups, ["interns"]);
+    let who = identify(&users.user, &users.group, None, ip("10.0.1.9"));
+    assert_eq!(who.name.as_deref(), Some("printer"));
+    assert_eq!(who.groups, ["interns"]);
+    let who = identify(&users.user, &users.group, None, ip("10.0.2.1"));
+    assert!(who.name.is_none() && who.groups.is_empty());
+}
+
+#[test]
+fn selector_match() {
+    let users = test_users();
+    let ip = "127.0.0.1".parse().unwrap();
+    let matched = |login: Option<&str>| {
+        let who = identify(&users.user, &users.group, login.map(str::to_owned), ip);
+        users
+            .rule
+            .iter()
+            .map(|rule| rule.is_match(&who))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(matched(None), [true, false, false, false]);
+    assert_eq!(matched(Some("alice")), [true, true, false, true]);
+    assert_eq!(matched(Some("bob")), [true, false, true, false]);
+    assert_eq!(matched(Some("carol")), [true, false, false, true]);
+    for rule in &users.rule {
+        assert!(rule.check(&users.group).is_ok());
+    }
+    let typo = Selector {
+        users: Vec::new(),
+        groups: vec!["intern".to_owned()],
+    };
+    assert_eq!(
+        typo.check(&users.group),
+        Err("unknown group intern".to_owned())
+    );
+}